@@ -0,0 +1,169 @@
+use std::{path::{Path, PathBuf}, fs::{self, File}};
+use xml::reader::{EventReader, XmlEvent as ReaderEvent};
+use xml::writer::{EmitterConfig, XmlEvent};
+
+use crate::{ChecksumMethod, FileMetadata, process_checksum, util::format_system_time_to_rfc3339, mhl::{CreatorInfo, write_event, write_text_element}};
+
+// Name of the folder holding the ASC MHL history of a directory.
+pub const ASCMHL_FOLDER: &str = "ascmhl";
+
+// Name of the chain file inside the ascmhl folder.
+const CHAIN_FILE: &str = "ascmhl_chain.xml";
+
+// A generation entry of the ascmhl_chain.xml file.
+struct ChainEntry {
+    sequence_nr: u32,
+    path: String,
+    c4: String,
+}
+
+// Writes a new generation to the ASC MHL history of the given root directory and adds it to the chain file.
+// The paths in the metadata are relative to the destination directory, the root has to be inside of it.
+pub fn write_ascmhl_generation(root: &Path, destination: &Path, metadata: &[FileMetadata], process: &str, start_date: &str, ignore_patterns: &[&str]) -> std::io::Result<PathBuf> {
+    let folder = root.join(ASCMHL_FOLDER);
+    fs::create_dir_all(&folder)?;
+
+    // Read the existing generations to find the next sequence number.
+    let chain_file = folder.join(CHAIN_FILE);
+    let mut chain = if chain_file.exists() { read_chain(&chain_file)? } else { Vec::new() };
+    let sequence_nr = chain.iter().map(|entry| entry.sequence_nr).max().unwrap_or(0) + 1;
+
+    // Generation file name is the sequence number + the name of the root directory + the current date and time + .mhl
+    let root_name = root.file_name().unwrap_or_default().to_string_lossy();
+    let file_name = format!("{:04}_{}_{}.mhl", sequence_nr, root_name, start_date.replace(':', "").replace('T', "_"));
+    let generation_file = folder.join(&file_name);
+
+    let root_prefix = root.strip_prefix(destination).unwrap_or(Path::new(""));
+    write_generation(&generation_file, root_prefix, metadata, process, start_date, ignore_patterns)?;
+
+    chain.push(ChainEntry {
+        sequence_nr,
+        path: file_name,
+        c4: c4_of_file(&generation_file)?,
+    });
+    write_chain(&chain_file, &chain)?;
+
+    Ok(generation_file)
+}
+
+// Writes a single ASC MHL generation file.
+fn write_generation(generation_file: &Path, root_prefix: &Path, metadata: &[FileMetadata], process: &str, start_date: &str, ignore_patterns: &[&str]) -> std::io::Result<()> {
+    let file = File::create(generation_file)?;
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(file);
+
+    write_event(&mut writer, XmlEvent::start_element("hashlist").attr("version", "2.0").default_ns("urn:ASC:MHL:v2.0"))?;
+
+    // Same creator as in the classic mhl file and the report. The tool name and version are separate here.
+    let creator = CreatorInfo::collect();
+    let version = env!("CARGO_PKG_VERSION");
+
+    write_event(&mut writer, XmlEvent::start_element("creatorinfo"))?;
+    write_text_element(&mut writer, "creationdate", start_date)?;
+    write_text_element(&mut writer, "hostname", &creator.hostname)?;
+    write_event(&mut writer, XmlEvent::start_element("tool").attr("version", version))?;
+    write_event(&mut writer, XmlEvent::characters(env!("CARGO_PKG_NAME")))?;
+    write_event(&mut writer, XmlEvent::end_element())?;
+    write_text_element(&mut writer, "author", &creator.name)?;
+    write_event(&mut writer, XmlEvent::end_element())?;
+
+    write_event(&mut writer, XmlEvent::start_element("processinfo"))?;
+    write_text_element(&mut writer, "process", process)?;
+    write_event(&mut writer, XmlEvent::start_element("ignore"))?;
+    for pattern in ignore_patterns {
+        write_text_element(&mut writer, "pattern", pattern)?;
+    }
+    write_event(&mut writer, XmlEvent::end_element())?;
+    write_event(&mut writer, XmlEvent::end_element())?;
+
+    write_event(&mut writer, XmlEvent::start_element("hashes"))?;
+    for item in metadata {
+        let file_path = Path::new(&item.file);
+        let relative_path = file_path.strip_prefix(root_prefix).unwrap_or(file_path).to_string_lossy();
+        let size = item.size.to_string();
+        let last_modification_date = format_system_time_to_rfc3339(item.last_modification_date);
+        let hash_date = format_system_time_to_rfc3339(item.hash_date);
+        let action = if item.verified { "verified" } else { "original" };
+
+        write_event(&mut writer, XmlEvent::start_element("hash"))?;
+        write_event(&mut writer, XmlEvent::start_element("path").attr("size", &size).attr("lastmodificationdate", &last_modification_date))?;
+        write_event(&mut writer, XmlEvent::characters(&relative_path))?;
+        write_event(&mut writer, XmlEvent::end_element())?;
+        for checksum in &item.checksums {
+            let Some(element) = checksum.method.ascmhl_name() else {
+                continue;
+            };
+            write_event(&mut writer, XmlEvent::start_element(element).attr("action", action).attr("hashdate", &hash_date))?;
+            write_event(&mut writer, XmlEvent::characters(&checksum.value))?;
+            write_event(&mut writer, XmlEvent::end_element())?;
+        }
+        write_event(&mut writer, XmlEvent::end_element())?;
+    }
+    write_event(&mut writer, XmlEvent::end_element())?;
+
+    write_event(&mut writer, XmlEvent::end_element())?;
+
+    Ok(())
+}
+
+// Reads the generations listed in an ascmhl_chain.xml file.
+fn read_chain(chain_file: &Path) -> std::io::Result<Vec<ChainEntry>> {
+    let reader = EventReader::new(File::open(chain_file)?);
+    let mut chain: Vec<ChainEntry> = Vec::new();
+    let mut current_element = String::new();
+
+    for event in reader {
+        match event.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))? {
+            ReaderEvent::StartElement { name, attributes, .. } => {
+                if name.local_name == "hashlist" {
+                    let sequence_nr = attributes.iter()
+                        .find(|attribute| attribute.name.local_name == "sequencenr")
+                        .and_then(|attribute| attribute.value.parse().ok())
+                        .unwrap_or(0);
+                    chain.push(ChainEntry { sequence_nr, path: String::new(), c4: String::new() });
+                }
+                current_element = name.local_name;
+            }
+            ReaderEvent::Characters(text) => {
+                if let Some(entry) = chain.last_mut() {
+                    match current_element.as_str() {
+                        "path" => entry.path = text,
+                        "c4" => entry.c4 = text,
+                        _ => {}
+                    }
+                }
+            }
+            ReaderEvent::EndElement { .. } => current_element.clear(),
+            _ => {}
+        }
+    }
+
+    Ok(chain)
+}
+
+// Writes the ascmhl_chain.xml file listing all generations and their C4 ids.
+fn write_chain(chain_file: &Path, chain: &[ChainEntry]) -> std::io::Result<()> {
+    let file = File::create(chain_file)?;
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(file);
+
+    write_event(&mut writer, XmlEvent::start_element("ascmhldirectory").default_ns("urn:ASC:MHL:DIRECTORY:v2.0"))?;
+    for entry in chain {
+        let sequence_nr = entry.sequence_nr.to_string();
+        write_event(&mut writer, XmlEvent::start_element("hashlist").attr("sequencenr", &sequence_nr))?;
+        write_text_element(&mut writer, "path", &entry.path)?;
+        write_text_element(&mut writer, "c4", &entry.c4)?;
+        write_event(&mut writer, XmlEvent::end_element())?;
+    }
+    write_event(&mut writer, XmlEvent::end_element())?;
+
+    Ok(())
+}
+
+// Calculates the C4 id (SMPTE ST 2114) of a file.
+pub fn c4_of_file(path: &Path) -> std::io::Result<String> {
+    let checksums = process_checksum(path, &[ChecksumMethod::C4], &mut |_| {})?;
+    Ok(checksums.into_iter().map(|checksum| checksum.value).collect())
+}
@@ -0,0 +1,222 @@
+use std::{path::Path, fs::Metadata};
+
+// Names of the extended attributes that hold POSIX ACLs on Linux. The default ACL only exists on directories.
+#[cfg(target_os = "linux")]
+const ACL_ATTRIBUTES: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+// Pseudo name of the ACL in the listings, on macOS it is not an extended attribute.
+#[cfg(target_os = "macos")]
+const ACL_NAME: &str = "acl";
+
+// Which metadata besides permissions and dates is copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AttributeOptions {
+    pub xattrs: bool,
+    pub acls: bool,
+}
+
+impl AttributeOptions {
+    pub fn any(&self) -> bool {
+        self.xattrs || self.acls
+    }
+}
+
+// Which owner and group the copies get. Mapped ids win over the ones of the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Ownership {
+    // Keep the owner and group of the source.
+    pub owner: bool,
+    pub group: bool,
+    // Give every copy this user and group id.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Ownership {
+    pub fn any(&self) -> bool {
+        self.owner || self.group || self.uid.is_some() || self.gid.is_some()
+    }
+}
+
+// Sets the owner and group of a destination file or directory. Symbolic links are changed themselves, not their target.
+// The metadata is the one of the source entry that was copied: of the link itself if it was copied as a link, of its target if it was followed.
+// Changing the owner usually requires root, everyone can change the group to one of their own groups.
+#[cfg(unix)]
+pub fn copy_ownership(metadata: &Metadata, destination_path: &Path, ownership: Ownership) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = ownership.uid.or(ownership.owner.then(|| metadata.uid()));
+    let gid = ownership.gid.or(ownership.group.then(|| metadata.gid()));
+    if uid.is_none() && gid.is_none() {
+        return Ok(());
+    }
+    std::os::unix::fs::lchown(destination_path, uid, gid)?;
+
+    // Changing the owner clears the setuid and setgid bits of files.
+    if metadata.is_file() && metadata.mode() & 0o6000 != 0 {
+        std::fs::set_permissions(destination_path, metadata.permissions())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn copy_ownership(_metadata: &Metadata, _destination_path: &Path, _ownership: Ownership) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Ownership can not be copied on this platform."))
+}
+
+// Looks up a user id by name. Numeric ids are taken as they are.
+#[cfg(unix)]
+pub fn user_id(name: &str) -> Option<u32> {
+    name.parse().ok().or_else(|| users::get_user_by_name(name).map(|user| user.uid()))
+}
+
+#[cfg(not(unix))]
+pub fn user_id(name: &str) -> Option<u32> {
+    name.parse().ok()
+}
+
+// Looks up a group id by name. Numeric ids are taken as they are.
+#[cfg(unix)]
+pub fn group_id(name: &str) -> Option<u32> {
+    name.parse().ok().or_else(|| users::get_group_by_name(name).map(|group| group.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn group_id(name: &str) -> Option<u32> {
+    name.parse().ok()
+}
+
+// True if an extended attribute is copied with the given options.
+// On Linux only the user namespace is copied, trusted attributes need root and security labels belong to the policy of the destination.
+#[cfg(target_os = "linux")]
+fn is_copied(name: &str, options: AttributeOptions) -> bool {
+    if ACL_ATTRIBUTES.contains(&name) {
+        options.acls
+    } else {
+        options.xattrs && name.starts_with("user.")
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_copied(_name: &str, options: AttributeOptions) -> bool {
+    options.xattrs
+}
+
+// Lists the extended attributes and ACLs of a file that are copied with the given options.
+#[cfg(unix)]
+pub fn list_attributes(path: &Path, options: AttributeOptions) -> std::io::Result<Vec<String>> {
+    let mut names: Vec<String> = xattr::list(path)?
+        .map(|name| name.to_string_lossy().to_string())
+        .filter(|name| is_copied(name, options))
+        .collect();
+    names.sort();
+
+    #[cfg(target_os = "macos")]
+    if options.acls && acl::has_acl(path)? {
+        names.push(ACL_NAME.to_string());
+    }
+
+    Ok(names)
+}
+
+#[cfg(not(unix))]
+pub fn list_attributes(_path: &Path, _options: AttributeOptions) -> std::io::Result<Vec<String>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Extended attributes are not supported on this platform."))
+}
+
+// Copies the extended attributes and ACLs of the input file to a destination file.
+// Every attribute is copied on its own, the ones that fail are returned with the error instead of stopping at the first.
+#[cfg(unix)]
+pub fn copy_attributes(input_path: &Path, destination_path: &Path, options: AttributeOptions) -> Vec<(String, std::io::Error)> {
+    let mut failures = Vec::new();
+
+    let names = match xattr::list(input_path) {
+        Ok(names) => names,
+        Err(e) => {
+            failures.push(("extended attributes".to_string(), e));
+            return failures;
+        }
+    };
+
+    for name in names {
+        let display_name = name.to_string_lossy().to_string();
+        if !is_copied(&display_name, options) {
+            continue;
+        }
+        // The attribute may be removed between listing and reading it.
+        let copied = xattr::get(input_path, &name).and_then(|value| match value {
+            Some(value) => xattr::set(destination_path, &name, &value),
+            None => Ok(()),
+        });
+        if let Err(e) = copied {
+            failures.push((display_name, e));
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    if options.acls {
+        if let Err(e) = acl::copy_acl(input_path, destination_path) {
+            failures.push((ACL_NAME.to_string(), e));
+        }
+    }
+
+    failures
+}
+
+#[cfg(not(unix))]
+pub fn copy_attributes(_input_path: &Path, _destination_path: &Path, _options: AttributeOptions) -> Vec<(String, std::io::Error)> {
+    vec![("extended attributes".to_string(), std::io::Error::new(std::io::ErrorKind::Unsupported, "Extended attributes are not supported on this platform."))]
+}
+
+// ACLs on macOS are not stored in extended attributes and have to be copied with the acl functions of libc.
+#[cfg(target_os = "macos")]
+mod acl {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+    use libc::{c_char, c_int, c_void};
+
+    // The only ACL type macOS supports, the NFSv4 style ACLs shown by "ls -le".
+    const ACL_TYPE_EXTENDED: c_int = 0x0000_0100;
+
+    extern "C" {
+        fn acl_get_file(path: *const c_char, acl_type: c_int) -> *mut c_void;
+        fn acl_set_file(path: *const c_char, acl_type: c_int, acl: *mut c_void) -> c_int;
+        fn acl_free(obj: *mut c_void) -> c_int;
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    // Reads the ACL of a file. Files without an ACL return None.
+    fn get(path: &Path) -> io::Result<Option<*mut c_void>> {
+        let path = c_path(path)?;
+        let acl = unsafe { acl_get_file(path.as_ptr(), ACL_TYPE_EXTENDED) };
+        if !acl.is_null() {
+            return Ok(Some(acl));
+        }
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() == Some(libc::ENOENT) {
+            return Ok(None);
+        }
+        Err(error)
+    }
+
+    pub fn has_acl(path: &Path) -> io::Result<bool> {
+        Ok(get(path)?.map(|acl| unsafe { acl_free(acl) }).is_some())
+    }
+
+    pub fn copy_acl(input_path: &Path, destination_path: &Path) -> io::Result<()> {
+        let Some(acl) = get(input_path)? else {
+            return Ok(());
+        };
+        let result = c_path(destination_path).and_then(|destination_path| {
+            match unsafe { acl_set_file(destination_path.as_ptr(), ACL_TYPE_EXTENDED, acl) } {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            }
+        });
+        unsafe { acl_free(acl) };
+        result
+    }
+}
@@ -0,0 +1,282 @@
+use std::{path::{Path, PathBuf}, fs::{self, File}, io::{Read, Write}, sync::mpsc, thread};
+use filetime_creation::FileTime;
+
+use crate::{CHUNK_SIZE, Checksum, ChecksumMethod, Hashers};
+
+// Wraps errors of reading the input file, so callers can tell them apart from errors writing the destinations.
+#[derive(Debug)]
+struct SourceError(std::io::Error);
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+fn source_error(error: std::io::Error) -> std::io::Error {
+    std::io::Error::new(error.kind(), SourceError(error))
+}
+
+// True if an error of copy_file happened while reading the input file, false if it happened in a destination.
+pub fn is_source_error(error: &std::io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<SourceError>())
+}
+
+// Number of buffers passed between the reader, hasher and writer. Each one holds a chunk.
+const BUFFER_COUNT: usize = 4;
+
+// Prefix of the hidden temporary files that are written before they are renamed to their final name.
+const TEMP_PREFIX: &str = ".rccopy-tmp-";
+
+// Copy a file from the input directory to one or more destinations. The input file is read only once.
+// Returns the checksums of the input file, one for every checksum method, and the result of every destination in the order of the destination paths.
+// A destination that can not be written fails on its own, the others are still written. Only errors reading the input file are returned as error,
+// they can be told apart from other errors with is_source_error.
+// The progress callback is called with the amount of bytes copied after every chunk.
+//
+// Reading, hashing and writing run in a pipeline: a reader thread fills buffers from a ring of reusable buffers,
+// a hasher thread updates the checksum and the writer (the calling thread) writes them to the destinations
+// and hands them back to the reader. The throughput is limited by the slowest of the three instead of their sum.
+pub fn copy_file(input_path: &Path, destination_paths: &[PathBuf], checksum_methods: &[ChecksumMethod], progress: &mut dyn FnMut(u64)) -> std::io::Result<(Vec<Checksum>, Vec<std::io::Result<()>>)> {
+
+    let hasher = (!checksum_methods.is_empty()).then(|| Hashers::new(checksum_methods));
+
+    // Open the input file.
+    let mut input_file = File::open(input_path).map_err(source_error)?;
+
+    // Create the destination files. A destination that fails is left out, the others are still written.
+    let mut results: Vec<std::io::Result<()>> = Vec::new();
+    let mut destination_files: Vec<Option<File>> = Vec::new();
+    for destination_path in destination_paths {
+        match create_destination_file(destination_path) {
+            Ok(file) => {
+                results.push(Ok(()));
+                destination_files.push(Some(file));
+            }
+            Err(e) => {
+                results.push(Err(e));
+                destination_files.push(None);
+            }
+        }
+    }
+
+    // Fill the ring with empty buffers.
+    let (free_sender, free_receiver) = mpsc::sync_channel::<Vec<u8>>(BUFFER_COUNT);
+    for _ in 0..BUFFER_COUNT {
+        let _ = free_sender.send(vec![0; CHUNK_SIZE]);
+    }
+
+    let checksum = thread::scope(|scope| -> std::io::Result<Vec<Checksum>> {
+        let (read_sender, read_receiver) = mpsc::sync_channel::<(Vec<u8>, usize)>(BUFFER_COUNT);
+
+        // Reader. Stops at the end of the file or once the writer is gone.
+        let reader = scope.spawn(move || -> std::io::Result<()> {
+            while let Ok(mut buffer) = free_receiver.recv() {
+                let bytes_read = input_file.read(&mut buffer).map_err(source_error)?;
+                if bytes_read == 0 || read_sender.send((buffer, bytes_read)).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        // Hasher, sitting between the reader and the writer. Every checksum is updated from the same buffer.
+        let (write_receiver, hasher) = match hasher {
+            Some(mut hasher) => {
+                let (hash_sender, hash_receiver) = mpsc::sync_channel::<(Vec<u8>, usize)>(BUFFER_COUNT);
+                let hasher = scope.spawn(move || {
+                    for (buffer, bytes_read) in read_receiver {
+                        hasher.update(&buffer[..bytes_read]);
+                        if hash_sender.send((buffer, bytes_read)).is_err() {
+                            break;
+                        }
+                    }
+                    hasher
+                });
+                (hash_receiver, Some(hasher))
+            }
+            None => (read_receiver, None),
+        };
+
+        // Writer. Returns every buffer to the reader once it is written to all destinations.
+        // A destination that fails is dropped, the writer stops once none is left.
+        for (buffer, bytes_read) in write_receiver.iter() {
+            for (destination_file, result) in destination_files.iter_mut().zip(&mut results) {
+                if let Some(Err(e)) = destination_file.as_mut().map(|file| file.write_all(&buffer[..bytes_read])) {
+                    *result = Err(e);
+                    *destination_file = None;
+                }
+            }
+            if destination_files.iter().all(Option::is_none) {
+                break;
+            }
+            progress(bytes_read as u64);
+            let _ = free_sender.send(buffer);
+        }
+
+        // Stop the other threads if the writer stopped early.
+        drop(write_receiver);
+        drop(free_sender);
+
+        let read_result = reader.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+        let hasher = hasher.map(|hasher| hasher.join().unwrap_or_else(|e| std::panic::resume_unwind(e)));
+
+        read_result?;
+
+        // Compute and return the checksums
+        Ok(hasher.map(Hashers::finalize).unwrap_or_default())
+    })?;
+
+    drop(destination_files);
+
+    // Copy the metadata
+    copy_metadata(input_path, destination_paths, &mut results)?;
+
+    Ok((checksum, results))
+}
+
+// Creates a destination file and the directories above it.
+fn create_destination_file(destination_path: &Path) -> std::io::Result<File> {
+    if let Some(parent) = destination_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(destination_path)
+}
+
+// Copies permissions and access, modification and creation dates of the input file to the destination files.
+// Destinations that already failed are left out, a destination whose metadata can not be set fails.
+fn copy_metadata(input_path: &Path, destination_paths: &[PathBuf], results: &mut [std::io::Result<()>]) -> std::io::Result<()> {
+    let metadata = fs::metadata(input_path).map_err(source_error)?;
+
+    let accessed = FileTime::from_last_access_time(&metadata);
+    let modified = FileTime::from_last_modification_time(&metadata);
+    let created = FileTime::from_creation_time(&metadata).unwrap_or(modified);
+
+    for (destination_path, result) in destination_paths.iter().zip(results) {
+        if result.is_ok() {
+            *result = fs::set_permissions(destination_path, metadata.permissions())
+                .and_then(|()| filetime_creation::set_file_times(destination_path, accessed, modified, created));
+        }
+    }
+
+    Ok(())
+}
+
+// Copies the access, modification and creation dates of a directory.
+// Creating or renaming anything inside the destination directory changes its modification date again, so this has to come last.
+pub fn copy_dir_times(input_path: &Path, destination_path: &Path) -> std::io::Result<()> {
+    let metadata = fs::metadata(input_path)?;
+
+    let accessed = FileTime::from_last_access_time(&metadata);
+    let modified = FileTime::from_last_modification_time(&metadata);
+    let created = FileTime::from_creation_time(&metadata).unwrap_or(modified);
+
+    filetime_creation::set_file_times(destination_path, accessed, modified, created)
+}
+
+// Recreates a symbolic link in the destinations, pointing to the same target. Relative targets stay relative.
+// Like files, the link is created under a temporary name first and then renamed.
+pub fn copy_link(input_path: &Path, destination_paths: &[PathBuf]) -> std::io::Result<()> {
+    let target = fs::read_link(input_path)?;
+    let metadata = fs::symlink_metadata(input_path)?;
+
+    let accessed = FileTime::from_last_access_time(&metadata);
+    let modified = FileTime::from_last_modification_time(&metadata);
+    let created = FileTime::from_creation_time(&metadata).unwrap_or(modified);
+
+    for destination_path in destination_paths {
+        if let Some(parent) = destination_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temp_file = temp_path(destination_path);
+        let _ = fs::remove_file(&temp_file);
+        create_symlink(&target, &temp_file, input_path)?;
+        filetime_creation::set_symlink_file_times(&temp_file, accessed, modified, created)?;
+
+        if let Err(e) = fs::rename(&temp_file, destination_path) {
+            let _ = fs::remove_file(&temp_file);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path, _input_path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+// Windows has different links for files and directories.
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path, input_path: &Path) -> std::io::Result<()> {
+    if fs::metadata(input_path).is_ok_and(|metadata| metadata.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+// Creates a new named pipe or device node in the destinations, with the same type, permissions, device number and dates as the input.
+// The input is never opened. A symbolic link to a named pipe or device, as found when following links, is recreated as what it points to.
+#[cfg(unix)]
+pub fn recreate_special_file(input_path: &Path, destination_paths: &[PathBuf]) -> std::io::Result<()> {
+    use std::{ffi::CString, os::unix::{ffi::OsStrExt, fs::MetadataExt}};
+
+    let metadata = fs::metadata(input_path)?;
+
+    let accessed = FileTime::from_last_access_time(&metadata);
+    let modified = FileTime::from_last_modification_time(&metadata);
+    let created = FileTime::from_creation_time(&metadata).unwrap_or(modified);
+
+    for destination_path in destination_paths {
+        if let Some(parent) = destination_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temp_file = temp_path(destination_path);
+        let _ = fs::remove_file(&temp_file);
+
+        let c_path = CString::new(temp_file.as_os_str().as_bytes()).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let result = unsafe { libc::mknod(c_path.as_ptr(), metadata.mode() as libc::mode_t, metadata.rdev() as libc::dev_t) };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let finished = fs::set_permissions(&temp_file, metadata.permissions())
+            // Setting the dates must not open the file, opening a named pipe blocks until someone writes to it.
+            .and_then(|()| filetime_creation::set_symlink_file_times(&temp_file, accessed, modified, created))
+            .and_then(|()| fs::rename(&temp_file, destination_path));
+        if let Err(e) = finished {
+            let _ = fs::remove_file(&temp_file);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn recreate_special_file(_input_path: &Path, _destination_paths: &[PathBuf]) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Special files can not be recreated on this platform."))
+}
+
+// Returns the hidden temporary file a destination file is written to before it is renamed. It is in the same directory, so the rename is atomic.
+pub fn temp_path(destination_path: &Path) -> PathBuf {
+    let file_name = destination_path.file_name().unwrap_or_default().to_string_lossy();
+    destination_path.with_file_name(format!("{}{}", TEMP_PREFIX, file_name))
+}
+
+// Returns the destination file a temporary file is renamed to.
+pub fn final_path(temp_path: &Path) -> PathBuf {
+    let file_name = temp_path.file_name().unwrap_or_default().to_string_lossy();
+    temp_path.with_file_name(file_name.strip_prefix(TEMP_PREFIX).unwrap_or(&file_name))
+}
+
+// True if the path is a temporary file written by copy_file.
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|file_name| file_name.to_string_lossy().starts_with(TEMP_PREFIX))
+}
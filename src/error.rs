@@ -0,0 +1,56 @@
+use std::{fmt, path::PathBuf};
+
+use crate::{ChecksumMethod, PreflightIssue};
+
+// Errors that stop a copy or verify job from running.
+#[derive(Debug)]
+pub enum Error {
+    InputNotFound(PathBuf),
+    InputNotDirectory(PathBuf),
+    NoDestination,
+    DestinationNotFound(PathBuf),
+    DestinationNotDirectory(PathBuf),
+    SameInputAndDestination(PathBuf),
+    DuplicateDestination(PathBuf),
+    InvalidChecksumMethod(String),
+    InvalidPattern(String),
+    UnknownUser(String),
+    UnknownGroup(String),
+    ChecksumRequired,
+    ChecksumNotInAscMhl(ChecksumMethod),
+    Preflight(Vec<PreflightIssue>),
+    Io { path: PathBuf, source: std::io::Error },
+    Mhl { path: PathBuf, source: std::io::Error },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InputNotFound(path) => write!(f, "Input directory {} does not exist.", path.display()),
+            Error::InputNotDirectory(path) => write!(f, "Input {} is not a directory.", path.display()),
+            Error::NoDestination => write!(f, "No destination directory given."),
+            Error::DestinationNotFound(path) => write!(f, "Destination directory {} does not exist.", path.display()),
+            Error::DestinationNotDirectory(path) => write!(f, "Destination {} is not a directory.", path.display()),
+            Error::SameInputAndDestination(path) => write!(f, "Input and destination directorys are the same: {}", path.display()),
+            Error::DuplicateDestination(path) => write!(f, "Destination {} was given more than once.", path.display()),
+            Error::InvalidChecksumMethod(method) => write!(f, "Invalid checksum method: {}", method),
+            Error::InvalidPattern(pattern) => write!(f, "Invalid pattern: {}", pattern),
+            Error::UnknownUser(name) => write!(f, "Unknown user: {}", name),
+            Error::UnknownGroup(name) => write!(f, "Unknown group: {}", name),
+            Error::ChecksumRequired => write!(f, "Writing an ASC MHL history requires a checksum method."),
+            Error::ChecksumNotInAscMhl(method) => write!(f, "ASC MHL does not support the checksum method {}, use md5, sha1, xxhash64, xxh3, xxh128 or c4.", method),
+            Error::Preflight(issues) => write!(f, "The pre-flight check found {} {}, nothing was copied.", issues.len(), if issues.len() == 1 { "problem" } else { "problems" }),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Mhl { path, source } => write!(f, "Could not read or write mhl file {}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } | Error::Mhl { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::{path::Path, fs};
+use glob::{MatchOptions, Pattern};
+
+use crate::{Error, EXCLUDE_FILES};
+
+// Name of the ignore file that is read from the root of the source directory.
+pub const IGNORE_FILE: &str = ".rccopyignore";
+
+// Wildcards do not match the path separator, "**" matches any number of directories.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+// A single include or exclude pattern.
+#[derive(Debug, Clone)]
+struct Rule {
+    text: String,
+    pattern: Pattern,
+    // The pattern ends with a slash and only matches directories.
+    dir_only: bool,
+    // The pattern contains a slash and is matched against the path relative to the source root instead of the name.
+    anchored: bool,
+}
+
+impl Rule {
+    fn new(text: &str) -> Result<Rule, Error> {
+        let dir_only = text.ends_with('/');
+        let trimmed = text.trim_end_matches('/');
+        let anchored = trimmed.contains('/');
+        let trimmed = trimmed.trim_start_matches('/');
+
+        if trimmed.is_empty() {
+            return Err(Error::InvalidPattern(text.to_string()));
+        }
+        let pattern = Pattern::new(trimmed).map_err(|_| Error::InvalidPattern(text.to_string()))?;
+
+        Ok(Rule { text: text.to_string(), pattern, dir_only, anchored })
+    }
+
+    // Checks the rule against a path relative to the source root.
+    fn matches(&self, relative_path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            return self.pattern.matches_path_with(relative_path, MATCH_OPTIONS);
+        }
+        relative_path.file_name().is_some_and(|name| self.pattern.matches_with(&name.to_string_lossy(), MATCH_OPTIONS))
+    }
+}
+
+// Decides which files of the source directory are copied.
+// Patterns are globs, similar to .gitignore: "*.tmp" matches a name anywhere, "CACHE/" only matches directories
+// and "A001/*.RMD" is matched against the path relative to the source root. Include patterns win over exclude patterns.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    excludes: Vec<Rule>,
+    includes: Vec<Rule>,
+}
+
+impl Filter {
+    // Creates a filter, optionally with the built-in list of macOS system files.
+    pub fn new(default_excludes: bool) -> Filter {
+        let mut filter = Filter { excludes: Vec::new(), includes: Vec::new() };
+        if default_excludes {
+            for pattern in EXCLUDE_FILES.iter().copied().chain(["._*"]) {
+                if let Ok(rule) = Rule::new(pattern) {
+                    filter.excludes.push(rule);
+                }
+            }
+        }
+        filter
+    }
+
+    pub fn exclude(&mut self, pattern: &str) -> Result<(), Error> {
+        self.excludes.push(Rule::new(pattern)?);
+        Ok(())
+    }
+
+    pub fn include(&mut self, pattern: &str) -> Result<(), Error> {
+        self.includes.push(Rule::new(pattern)?);
+        Ok(())
+    }
+
+    // Reads the patterns of an ignore file. Every line is an exclude pattern, lines starting with "!" are include patterns.
+    // Empty lines and lines starting with "#" are skipped.
+    pub fn read_ignore_file(&mut self, ignore_file: &Path) -> Result<(), Error> {
+        let content = fs::read_to_string(ignore_file).map_err(|source| Error::Io { path: ignore_file.to_path_buf(), source })?;
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix('!') {
+                Some(pattern) => self.include(pattern)?,
+                None => self.exclude(line)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // True if a file or directory, given relative to the source root, is excluded.
+    pub fn is_excluded(&self, relative_path: &Path, is_dir: bool) -> bool {
+        self.excludes.iter().any(|rule| rule.matches(relative_path, is_dir))
+            && !self.includes.iter().any(|rule| rule.matches(relative_path, is_dir))
+    }
+
+    // The exclude patterns, as listed in the ignore section of ASC MHL generations.
+    pub fn exclude_patterns(&self) -> Vec<&str> {
+        self.excludes.iter().map(|rule| rule.text.as_str()).collect()
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::new(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(excludes: &[&str], includes: &[&str]) -> Filter {
+        let mut filter = Filter::new(false);
+        for pattern in excludes {
+            filter.exclude(pattern).unwrap();
+        }
+        for pattern in includes {
+            filter.include(pattern).unwrap();
+        }
+        filter
+    }
+
+    #[test]
+    fn name_patterns_match_anywhere() {
+        let filter = filter(&["*.tmp"], &[]);
+        assert!(filter.is_excluded(Path::new("A001/render.tmp"), false));
+        assert!(filter.is_excluded(Path::new("A001/Clips/render.tmp"), false));
+        assert!(!filter.is_excluded(Path::new("A001/Clips/A001C001.mov"), false));
+    }
+
+    #[test]
+    fn include_wins_over_exclude() {
+        let filter = filter(&["*.RMD", "._*"], &["keep.RMD"]);
+        assert!(filter.is_excluded(Path::new("A001/A001C001.RMD"), false));
+        assert!(!filter.is_excluded(Path::new("A001/keep.RMD"), false));
+        assert!(filter.is_excluded(Path::new("A001/._keep.RMD"), false));
+    }
+
+    #[test]
+    fn directory_patterns_only_prune_directories() {
+        let filter = filter(&["CACHE/"], &[]);
+        assert!(filter.is_excluded(Path::new("A001/CACHE"), true));
+        assert!(!filter.is_excluded(Path::new("A001/CACHE"), false));
+        assert!(!filter.is_excluded(Path::new("A001/CACHE.mov"), false));
+    }
+
+    #[test]
+    fn patterns_with_a_slash_are_anchored() {
+        let filter = filter(&["A001/*.RMD", "**/proxy/"], &[]);
+        assert!(filter.is_excluded(Path::new("A001/A001C001.RMD"), false));
+        assert!(!filter.is_excluded(Path::new("A001/Clips/A001C001.RMD"), false));
+        assert!(!filter.is_excluded(Path::new("B001/B001C001.RMD"), false));
+        assert!(filter.is_excluded(Path::new("A001/Clips/proxy"), true));
+    }
+
+    #[test]
+    fn default_excludes_can_be_included_again() {
+        let mut filter = Filter::new(true);
+        assert!(filter.is_excluded(Path::new("A001/.DS_Store"), false));
+        assert!(filter.is_excluded(Path::new("A001/._A001C001.mov"), false));
+        assert!(filter.is_excluded(Path::new("A001/.Spotlight-V100"), true));
+
+        filter.include(".DS_Store").unwrap();
+        assert!(!filter.is_excluded(Path::new("A001/.DS_Store"), false));
+        assert!(!Filter::new(false).is_excluded(Path::new("A001/.DS_Store"), false));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(matches!(Filter::new(false).exclude("/"), Err(Error::InvalidPattern(_))));
+        assert!(matches!(Filter::new(false).exclude("[a-"), Err(Error::InvalidPattern(_))));
+    }
+}
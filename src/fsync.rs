@@ -0,0 +1,57 @@
+use std::{path::Path, fs::File};
+
+// Alignment of the buffers, offsets and sizes of direct I/O reads. Covers the block size of common disks.
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+// Writes the data and metadata of a file to the disk.
+pub fn sync_file(path: &Path) -> std::io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+// Writes a directory entry to the disk, so a file created or renamed in it survives a crash.
+#[cfg(unix)]
+pub fn sync_dir(path: &Path) -> std::io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+// Directories can not be opened for syncing on this platform, the file system takes care of it.
+#[cfg(not(unix))]
+pub fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Drops a file from the page cache, so the next read comes from the disk. The file has to be synced first.
+#[cfg(target_os = "linux")]
+pub fn drop_from_cache(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = File::open(path)?;
+    let result = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if result != 0 {
+        return Err(std::io::Error::from_raw_os_error(result));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_from_cache(_path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Dropping files from the page cache is not supported on this platform."))
+}
+
+// Opens a file for reading with direct I/O, so reads bypass the page cache and come from the disk.
+// Returns None if the file system does not support direct I/O, e.g. tmpfs.
+#[cfg(target_os = "linux")]
+pub fn open_direct(path: &Path) -> std::io::Result<Option<File>> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    match std::fs::OpenOptions::new().read(true).custom_flags(libc::O_DIRECT).open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open_direct(_path: &Path) -> std::io::Result<Option<File>> {
+    Ok(None)
+}
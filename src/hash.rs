@@ -0,0 +1,300 @@
+use std::{path::Path, fs::File, io::{Read, Seek, SeekFrom}, fmt, str::FromStr};
+use clap::ValueEnum;
+use xxhash_rust::{xxh64::Xxh64, xxh3::Xxh3};
+use md5::{Md5, Digest};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use crate::{CHUNK_SIZE, Error, fsync::{open_direct, DIRECT_IO_ALIGNMENT}};
+
+// Alphabet used for the base58 encoding of C4 ids.
+const C4_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// Checksum methods supported by rccopy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum ChecksumMethod {
+    Md5,
+    Sha1,
+    #[value(alias = "xxh64")]
+    Xxhash64,
+    Xxh3,
+    Xxh128,
+    Sha256,
+    C4,
+    Blake3,
+}
+
+impl ChecksumMethod {
+    // Name of the checksum method as used on the command line and in the journal.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumMethod::Md5 => "md5",
+            ChecksumMethod::Sha1 => "sha1",
+            ChecksumMethod::Xxhash64 => "xxhash64",
+            ChecksumMethod::Xxh3 => "xxh3",
+            ChecksumMethod::Xxh128 => "xxh128",
+            ChecksumMethod::Sha256 => "sha256",
+            ChecksumMethod::C4 => "c4",
+            ChecksumMethod::Blake3 => "blake3",
+        }
+    }
+
+    // Element name of the checksum in classic mhl files.
+    pub fn mhl_name(&self) -> &'static str {
+        match self {
+            ChecksumMethod::Xxhash64 => "xxhash64be",
+            other => other.name(),
+        }
+    }
+
+    // Element name of the checksum in ASC MHL files. The ASC MHL schema has no element for sha256 and blake3.
+    pub fn ascmhl_name(&self) -> Option<&'static str> {
+        match self {
+            ChecksumMethod::Xxhash64 => Some("xxh64"),
+            ChecksumMethod::Sha256 | ChecksumMethod::Blake3 => None,
+            other => Some(other.name()),
+        }
+    }
+
+    // Finds the checksum method of a name used on the command line, in a classic mhl or in an ASC MHL file.
+    pub fn from_mhl_name(name: &str) -> Option<ChecksumMethod> {
+        match name {
+            "xxhash64" | "xxhash64be" | "xxh64" => Some(ChecksumMethod::Xxhash64),
+            other => ChecksumMethod::value_variants().iter().copied().find(|method| method.name() == other),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ChecksumMethod {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<ChecksumMethod, Error> {
+        ChecksumMethod::from_mhl_name(name).ok_or_else(|| Error::InvalidChecksumMethod(name.to_string()))
+    }
+}
+
+// A checksum and the method it was calculated with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub method: ChecksumMethod,
+    pub value: String,
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.value, self.method)
+    }
+}
+
+// Formats a list of checksums for the output, e.g. "d41d8cd9... (md5), 99aa06d3... (xxh128)".
+pub(crate) fn format_checksums(checksums: &[Checksum]) -> String {
+    checksums.iter().map(Checksum::to_string).collect::<Vec<String>>().join(", ")
+}
+
+// Running hash of a single checksum method.
+pub enum HashMethod {
+    Md5(Md5),
+    Sha1(Sha1),
+    Xxh64(Xxh64),
+    Xxh3(Box<Xxh3>),
+    Xxh128(Box<Xxh3>),
+    Sha256(Sha256),
+    C4(Sha512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl HashMethod {
+    // Creates a new hasher for the given checksum method.
+    pub fn new(checksum_method: ChecksumMethod) -> HashMethod {
+        match checksum_method {
+            ChecksumMethod::Md5 => HashMethod::Md5(Md5::new()),
+            ChecksumMethod::Sha1 => HashMethod::Sha1(Sha1::new()),
+            ChecksumMethod::Xxhash64 => HashMethod::Xxh64(Xxh64::new(0)),
+            ChecksumMethod::Xxh3 => HashMethod::Xxh3(Box::new(Xxh3::new())),
+            ChecksumMethod::Xxh128 => HashMethod::Xxh128(Box::new(Xxh3::new())),
+            ChecksumMethod::Sha256 => HashMethod::Sha256(Sha256::new()),
+            ChecksumMethod::C4 => HashMethod::C4(Sha512::new()),
+            ChecksumMethod::Blake3 => HashMethod::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    // Update hash
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            HashMethod::Md5(h) => h.update(data),
+            HashMethod::Sha1(h) => h.update(data),
+            HashMethod::Xxh64(h) => h.update(data),
+            HashMethod::Xxh3(h) | HashMethod::Xxh128(h) => h.update(data),
+            HashMethod::Sha256(h) => h.update(data),
+            HashMethod::C4(h) => h.update(data),
+            HashMethod::Blake3(h) => {
+                h.update(data);
+            }
+        };
+    }
+
+    // Compute and return the checksum as hex string, or as C4 id.
+    pub fn finalize(self) -> String {
+        match self {
+            HashMethod::Md5(h) => format!("{:032x}", h.finalize()),
+            HashMethod::Sha1(h) => format!("{:040x}", h.finalize()),
+            HashMethod::Xxh64(h) => format!("{:016x}", h.digest()),
+            HashMethod::Xxh3(h) => format!("{:016x}", h.digest()),
+            HashMethod::Xxh128(h) => format!("{:032x}", h.digest128()),
+            HashMethod::Sha256(h) => format!("{:064x}", h.finalize()),
+            HashMethod::C4(h) => c4_encode(&h.finalize()),
+            HashMethod::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+// Running hashes of several checksum methods, all updated from the same data so the file is only read once.
+pub struct Hashers {
+    hashers: Vec<(ChecksumMethod, HashMethod)>,
+}
+
+impl Hashers {
+    pub fn new(checksum_methods: &[ChecksumMethod]) -> Hashers {
+        Hashers { hashers: checksum_methods.iter().map(|&method| (method, HashMethod::new(method))).collect() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashers.is_empty()
+    }
+
+    // Update every hash
+    pub fn update(&mut self, data: &[u8]) {
+        for (_, hasher) in &mut self.hashers {
+            hasher.update(data);
+        }
+    }
+
+    // Compute and return the checksums, in the order of the checksum methods.
+    pub fn finalize(self) -> Vec<Checksum> {
+        self.hashers.into_iter().map(|(method, hasher)| Checksum { method, value: hasher.finalize() }).collect()
+    }
+}
+
+// Process the checksums of a file in a single pass. The progress callback is called with the amount of bytes read after every chunk.
+pub fn process_checksum(input_file: &Path, checksum_methods: &[ChecksumMethod], progress: &mut dyn FnMut(u64)) -> std::io::Result<Vec<Checksum>> {
+
+    let mut hasher = Hashers::new(checksum_methods);
+    let mut buffer = vec![0; CHUNK_SIZE];
+
+    // Open the input file.
+    let mut input_file = File::open(input_file)?;
+
+    // Calculate the checksum of the file.
+    loop {
+        let bytes_read = input_file.read(&mut buffer)?;
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..bytes_read]);
+        progress(bytes_read as u64);
+    }
+
+    Ok(hasher.finalize())
+}
+
+// Process the checksums of a file like process_checksum, but read it with direct I/O so the data comes from the disk and not from the page cache.
+// Returns None if direct I/O is not supported for the file.
+pub fn process_checksum_direct(input_path: &Path, checksum_methods: &[ChecksumMethod], progress: &mut dyn FnMut(u64)) -> std::io::Result<Option<Vec<Checksum>>> {
+
+    let mut hasher = Hashers::new(checksum_methods);
+
+    let Some(mut input_file) = open_direct(input_path)? else {
+        return Ok(None);
+    };
+
+    // Direct I/O needs a buffer that is aligned in memory. The chunk size is a multiple of the alignment.
+    let mut storage = vec![0; CHUNK_SIZE + DIRECT_IO_ALIGNMENT];
+    let offset = storage.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+    let buffer = &mut storage[offset..offset + CHUNK_SIZE];
+
+    let mut position: u64 = 0;
+    let mut direct = true;
+    loop {
+        let bytes_read = match input_file.read(buffer) {
+            Ok(bytes_read) => bytes_read,
+            // Some file systems, e.g. some FUSE mounts, accept direct I/O when opening the file but refuse the reads.
+            Err(e) if direct && position == 0 && e.kind() == std::io::ErrorKind::InvalidInput => return Ok(None),
+            // Others refuse a later read, e.g. after a short read that left the position unaligned. The rest of the file is read through the page cache.
+            Err(e) if direct && e.kind() == std::io::ErrorKind::InvalidInput => {
+                input_file = File::open(input_path)?;
+                input_file.seek(SeekFrom::Start(position))?;
+                direct = false;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        position += bytes_read as u64;
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..bytes_read]);
+        progress(bytes_read as u64);
+    }
+
+    Ok(Some(hasher.finalize()))
+}
+
+// Encodes a SHA-512 digest as C4 id (SMPTE ST 2114): "c4" followed by the base58 encoded digest, padded to 90 characters.
+fn c4_encode(digest: &[u8]) -> String {
+    let mut number = digest.to_vec();
+    let mut encoded: Vec<u8> = Vec::new();
+
+    while number.iter().any(|&byte| byte != 0) {
+        let mut remainder: u32 = 0;
+        for byte in number.iter_mut() {
+            let value = (remainder << 8) | *byte as u32;
+            *byte = (value / 58) as u8;
+            remainder = value % 58;
+        }
+        encoded.push(C4_ALPHABET[remainder as usize]);
+    }
+
+    while encoded.len() < 88 {
+        encoded.push(b'1');
+    }
+    encoded.reverse();
+
+    format!("c4{}", String::from_utf8_lossy(&encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c4_of(data: &[u8]) -> String {
+        let mut hasher = HashMethod::new(ChecksumMethod::C4);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    // Known answers from the C4 reference implementation.
+    #[test]
+    fn c4_known_answers() {
+        assert_eq!(c4_of(b""), "c459dsjfscH38cYeXXYogktxf4Cd9ibshE3BHUo6a58hBXmRQdZrAkZzsWcbWtDg5oQstpDuni4Hirj75GEmTc1sFT");
+        assert_eq!(c4_of(b"foo"), "c45xZeXwMSpqXjpDumcHMA6mhoAmGHkUo7r9WmN2UgSEQzj9KjgseaQdkEJ11fGb5S1WEENcV3q8RFWwEeVpC7Fjk2");
+    }
+
+    #[test]
+    fn c4_is_padded_to_90_characters() {
+        assert_eq!(c4_encode(&[0; 64]), format!("c4{}", "1".repeat(88)));
+
+        let mut digest = [0; 64];
+        digest[63] = 57;
+        assert_eq!(c4_encode(&digest), format!("c4{}z", "1".repeat(87)));
+    }
+}
@@ -0,0 +1,150 @@
+use std::{path::Path, fs, fmt::Write, time::{Duration, SystemTime}};
+
+use crate::{ChecksumMethod, CopyResult, DestinationResult, RecordStatus, mhl::CreatorInfo, util::{format_bytes, format_bytes_per_second, format_duration, format_system_time_to_rfc3339, mount_point}};
+
+// Styles of the report. Kept inline, so the report is a single file that can be printed or mailed as it is.
+const STYLE: &str = "
+body { font-family: -apple-system, 'Helvetica Neue', Arial, sans-serif; font-size: 13px; color: #222; margin: 2em; }
+h1 { font-size: 22px; margin-bottom: 0.2em; }
+h2 { font-size: 16px; margin-top: 2em; border-bottom: 1px solid #ccc; padding-bottom: 0.2em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 3px 8px; border-bottom: 1px solid #eee; vertical-align: top; }
+th { background: #f4f4f4; }
+table.info th { width: 12em; }
+td.number { text-align: right; white-space: nowrap; }
+.checksum { font-family: Menlo, Consolas, monospace; font-size: 11px; }
+.status { font-size: 16px; font-weight: bold; padding: 0.6em 1em; margin: 1em 0; border-radius: 4px; }
+.status.ok { background: #e3f5e1; color: #1d6b18; }
+.status.failed { background: #fde2e1; color: #a0110b; }
+.failures { border: 2px solid #d9302c; border-radius: 4px; padding: 0 1em 1em; }
+.failures h2 { color: #a0110b; border-bottom-color: #d9302c; }
+tr.failed td { color: #a0110b; }
+@media print { body { margin: 0; } .failures, tr { break-inside: avoid; } }
+";
+
+// Writes a printable report of a finished copy job as a single HTML file: job info, volumes, totals, every file with its checksums and the failures.
+pub fn write_html_report(path: &Path, input: &Path, checksum_methods: &[ChecksumMethod], result: &CopyResult) -> std::io::Result<()> {
+    let creator = CreatorInfo::collect();
+    let input_name = input.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| input.display().to_string());
+    let failed_files: usize = result.destinations.iter().map(|destination| destination.failed.len()).sum();
+    let metadata_failures: usize = result.destinations.iter().map(|destination| destination.metadata_failures.len()).sum();
+
+    let mut html = String::new();
+    let _ = writeln!(html, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">");
+    let _ = writeln!(html, "<title>Offload report {}</title>\n<style>{}</style>\n</head>\n<body>", escape(&input_name), STYLE);
+    let _ = writeln!(html, "<h1>Offload report: {}</h1>", escape(&input_name));
+
+    if result.had_errors() {
+        let _ = writeln!(html, "<div class=\"status failed\">Finished with errors: {} failed {}.</div>", failed_files, if failed_files == 1 { "file" } else { "files" });
+    } else {
+        let _ = writeln!(html, "<div class=\"status ok\">All files were copied{} successfully.</div>", if checksum_methods.is_empty() { "" } else { " and verified" });
+    }
+
+    // Failures come first, so they are not missed on the printout.
+    if failed_files > 0 || metadata_failures > 0 {
+        let _ = writeln!(html, "<div class=\"failures\">\n<h2>Failures</h2>\n<table>\n<tr><th>Destination</th><th>File</th><th>Problem</th></tr>");
+        for destination in &result.destinations {
+            for file in &destination.failed {
+                let error = destination.records.iter()
+                    .find(|record| record.source == *file)
+                    .and_then(|record| record.error.clone())
+                    .unwrap_or_else(|| "Could not be copied".to_string());
+                let _ = writeln!(html, "<tr class=\"failed\"><td>{}</td><td>{}</td><td>{}</td></tr>", escape(&destination.path.display().to_string()), escape(&file.display().to_string()), escape(&error));
+            }
+            for (file, error) in &destination.metadata_failures {
+                let _ = writeln!(html, "<tr><td>{}</td><td>{}</td><td>Metadata not copied: {}</td></tr>", escape(&destination.path.display().to_string()), escape(&file.display().to_string()), escape(error));
+            }
+        }
+        let _ = writeln!(html, "</table>\n</div>");
+    }
+
+    let checksum_names = checksum_methods.iter().map(ChecksumMethod::name).collect::<Vec<&str>>().join(", ");
+    let _ = writeln!(html, "<h2>Job</h2>\n<table class=\"info\">");
+    info_row(&mut html, "Source", &input.display().to_string());
+    info_row(&mut html, "Source volume", &volume_name(input));
+    info_row(&mut html, "Started", &result.start_date);
+    info_row(&mut html, "Finished", &format_system_time_to_rfc3339(SystemTime::now()));
+    info_row(&mut html, "Duration", &format_duration(result.duration));
+    info_row(&mut html, "Checksums", if checksum_names.is_empty() { "none" } else { &checksum_names });
+    info_row(&mut html, "Operator", &format!("{} ({})", creator.name, creator.username));
+    info_row(&mut html, "Host", &creator.hostname);
+    info_row(&mut html, "Tool", &creator.tool);
+    let _ = writeln!(html, "</table>");
+
+    let _ = writeln!(html, "<h2>Destinations</h2>\n<table>");
+    let _ = writeln!(html, "<tr><th>Destination</th><th>Volume</th><th>Copied</th><th>Skipped</th><th>Failed</th><th>Size copied</th><th>Average speed</th><th>MHL file</th></tr>");
+    for destination in &result.destinations {
+        let _ = writeln!(html, "<tr><td>{}</td><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td>{}</td></tr>",
+            escape(&destination.path.display().to_string()),
+            escape(&volume_name(&destination.path)),
+            destination.copied.len(),
+            destination.skipped.len(),
+            destination.failed.len(),
+            format_bytes(destination.bytes_copied()),
+            average_speed(destination, result.duration),
+            escape(&destination.mhl_file.as_ref().and_then(|file| file.file_name()).map(|name| name.to_string_lossy().to_string()).unwrap_or_default()));
+    }
+    let _ = writeln!(html, "</table>");
+
+    for destination in &result.destinations {
+        let _ = writeln!(html, "<h2>Files in {}</h2>\n<table>", escape(&destination.path.display().to_string()));
+        let _ = writeln!(html, "<tr><th>File</th><th>Size</th><th>Status</th><th>Checksums</th><th>Duration</th></tr>");
+        for record in &destination.records {
+            let file = record.destination.strip_prefix(&destination.path).unwrap_or(&record.destination);
+            let checksums = record.checksums.iter()
+                .map(|checksum| format!("{}: {}", checksum.method.name(), escape(&checksum.value)))
+                .collect::<Vec<String>>()
+                .join("<br>");
+            let _ = writeln!(html, "<tr{}><td>{}</td><td class=\"number\">{}</td><td>{}</td><td class=\"checksum\">{}</td><td class=\"number\">{}</td></tr>",
+                if record.status == RecordStatus::Failed { " class=\"failed\"" } else { "" },
+                escape(&file.display().to_string()),
+                format_bytes(record.size),
+                record.status.name(),
+                checksums,
+                format_duration(record.duration));
+        }
+        let _ = writeln!(html, "</table>");
+    }
+
+    let _ = writeln!(html, "</body>\n</html>");
+
+    fs::write(path, html)
+}
+
+fn info_row(html: &mut String, name: &str, value: &str) {
+    let _ = writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", name, escape(value));
+}
+
+// The volume a path is on, shown as the name of its mount point, e.g. "A001" for /Volumes/A001/Clips.
+fn volume_name(path: &Path) -> String {
+    let mount_point = mount_point(path);
+    match mount_point.file_name() {
+        Some(name) => format!("{} ({})", name.to_string_lossy(), mount_point.display()),
+        None => mount_point.display().to_string(),
+    }
+}
+
+// Bytes copied to a destination divided by the duration of the job.
+fn average_speed(destination: &DestinationResult, duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds <= 0.0 {
+        return "-".to_string();
+    }
+    format_bytes_per_second((destination.bytes_copied() as f64 / seconds) as u64)
+}
+
+// Escapes text for HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,1584 @@
+use std::{path::{Path, PathBuf}, fs, fmt, time::{Duration, Instant, SystemTime}, thread, sync::{mpsc, atomic::{AtomicBool, AtomicUsize, Ordering}}, collections::{BTreeMap, HashMap}};
+
+use crate::{AttributeOptions, Checksum, ChecksumMethod, Error, FileMetadata, MhlEntry, SpecialFileMode, SpecialKind, SymlinkMode, Ownership, copy_attributes, copy_file, copy_link, copy_ownership, group_id, is_source_error, list_attributes, user_id, recreate_special_file, process_checksum, process_checksum_direct, read_mhl, write_mhl_v2, preflight_check, copy::{copy_dir_times, final_path, temp_path, is_temp_file}, filter::{Filter, IGNORE_FILE}, fsync::{sync_file, sync_dir, drop_from_cache}};
+use crate::{ascmhl, journal::{Journal, JournalEntry, journal_time}, scan::scan_directory, util::{format_system_time_to_rfc3339, SpeedLine}, hash::format_checksums};
+use crate::html::write_html_report;
+use crate::report::{Event, EventCallback, FailureKind, FileRecord, ProgressMode, RecordStatus, ReportFormat, json_event, checksums_json, path_json, destination_totals_json, write_json_report};
+use serde_json::json;
+
+// Copies an input directory to one or more destination directories.
+//
+// let result = CopyJob::new("/Volumes/A001")
+//     .destination("/Volumes/Shuttle")
+//     .destination("/Volumes/RAID")
+//     .checksum(ChecksumMethod::Xxhash64)
+//     .mhl(true)
+//     .on_event(|event| println!("{:?}", event))
+//     .run()?;
+#[derive(Debug, Clone)]
+pub struct CopyJob {
+    input: PathBuf,
+    destinations: Vec<PathBuf>,
+    checksums: Vec<ChecksumMethod>,
+    mhl: bool,
+    ascmhl: bool,
+    dry_run: bool,
+    jobs: usize,
+    resume: bool,
+    fail_fast: bool,
+    force: bool,
+    fsync: Option<bool>,
+    drop_cache: bool,
+    verify_direct: bool,
+    trust_size_and_mtime: bool,
+    excludes: Vec<String>,
+    includes: Vec<String>,
+    default_excludes: bool,
+    symlinks: SymlinkMode,
+    special_files: SpecialFileMode,
+    attributes: AttributeOptions,
+    owner: bool,
+    group: bool,
+    map_user: Option<String>,
+    map_group: Option<String>,
+    progress: ProgressMode,
+    on_event: EventCallback,
+    report: Option<(ReportFormat, PathBuf)>,
+    html_report: bool,
+}
+
+// Result of a copy job.
+#[derive(Debug, Clone, Default)]
+pub struct CopyResult {
+    pub destinations: Vec<DestinationResult>,
+    // Source files that were not copied because of the exclude patterns.
+    pub excluded: Vec<PathBuf>,
+    // Source directories that were skipped as a whole because of the exclude patterns.
+    pub excluded_dirs: Vec<PathBuf>,
+    // Symbolic links that were not copied, because of the symlink mode or because they are broken or lead back into a parent directory.
+    pub skipped_links: Vec<PathBuf>,
+    // Named pipes, sockets and devices that were not copied.
+    pub skipped_special_files: Vec<(PathBuf, SpecialKind)>,
+    // Start of the job as RFC 3339 date, as written to the mhl files.
+    pub start_date: String,
+    pub duration: Duration,
+    pub dry_run: bool,
+    // True if the job stopped at the first error because of fail_fast. The files, links and directories after it were not copied.
+    pub stopped: bool,
+    // Error of writing the report given with report(). The files were still copied.
+    pub report_error: Option<String>,
+}
+
+// Result of a single destination of a copy job.
+#[derive(Debug, Clone)]
+pub struct DestinationResult {
+    pub path: PathBuf,
+    // Source files that were copied (and verified, if a checksum method was given).
+    pub copied: Vec<PathBuf>,
+    // Source files that already existed in the destination and were verified to be identical.
+    pub skipped: Vec<PathBuf>,
+    // Source files that could not be copied or verified.
+    pub failed: Vec<PathBuf>,
+    // Source symbolic links that were recreated as links. They are not listed in the mhl files.
+    pub links: Vec<PathBuf>,
+    // Source named pipes and devices that were recreated. They are not listed in the mhl files.
+    pub special_files: Vec<PathBuf>,
+    // Source files and directories that were copied, but some of their metadata could not be, e.g. the owner or an extended attribute.
+    pub metadata_failures: Vec<(PathBuf, String)>,
+    // Every file with its status, size, checksums, duration and error, in the order of the files.
+    pub records: Vec<FileRecord>,
+    // Checksums of the copied and skipped files.
+    pub hashes: Vec<FileMetadata>,
+    pub mhl_file: Option<PathBuf>,
+    pub ascmhl_generation: Option<PathBuf>,
+    pub html_report: Option<PathBuf>,
+}
+
+impl CopyResult {
+    // True if any file failed in any destination or the report could not be written.
+    pub fn had_errors(&self) -> bool {
+        self.destinations.iter().any(|destination| !destination.failed.is_empty()) || self.report_error.is_some()
+    }
+
+    // True if any file failed for the given reason in any destination.
+    pub fn has_failure(&self, failure: FailureKind) -> bool {
+        self.destinations.iter().any(|destination| destination.records.iter().any(|record| record.failure == Some(failure)))
+    }
+
+    // True if any file was copied to any destination.
+    pub fn copied_anything(&self) -> bool {
+        self.destinations.iter().any(|destination| !destination.copied.is_empty() || !destination.links.is_empty() || !destination.special_files.is_empty())
+    }
+}
+
+impl DestinationResult {
+    // Size of all files copied to this destination. Files that already existed are not counted.
+    pub fn bytes_copied(&self) -> u64 {
+        self.records.iter().filter(|record| record.status == RecordStatus::Copied).map(|record| record.size).sum()
+    }
+}
+
+impl CopyJob {
+    pub fn new(input: impl Into<PathBuf>) -> CopyJob {
+        CopyJob {
+            input: input.into(),
+            destinations: Vec::new(),
+            checksums: Vec::new(),
+            mhl: false,
+            ascmhl: false,
+            dry_run: false,
+            jobs: 1,
+            resume: false,
+            fail_fast: false,
+            force: false,
+            fsync: None,
+            drop_cache: false,
+            verify_direct: false,
+            trust_size_and_mtime: false,
+            excludes: Vec::new(),
+            includes: Vec::new(),
+            default_excludes: true,
+            symlinks: SymlinkMode::default(),
+            special_files: SpecialFileMode::default(),
+            attributes: AttributeOptions::default(),
+            owner: false,
+            group: false,
+            map_user: None,
+            map_group: None,
+            progress: ProgressMode::default(),
+            on_event: EventCallback::default(),
+            report: None,
+            html_report: false,
+        }
+    }
+
+    // Adds a destination directory. Can be called multiple times, the input is only read once.
+    pub fn destination(mut self, destination: impl Into<PathBuf>) -> CopyJob {
+        self.destinations.push(destination.into());
+        self
+    }
+
+    // Adds a checksum method. Can be called multiple times, all checksums are calculated in the same pass.
+    pub fn checksum(mut self, checksum: ChecksumMethod) -> CopyJob {
+        if !self.checksums.contains(&checksum) {
+            self.checksums.push(checksum);
+        }
+        self
+    }
+
+    // Write a mhl file to each destination directory.
+    pub fn mhl(mut self, mhl: bool) -> CopyJob {
+        self.mhl = mhl;
+        self
+    }
+
+    // Create or update an ASC MHL history in the copied directory of each destination.
+    pub fn ascmhl(mut self, ascmhl: bool) -> CopyJob {
+        self.ascmhl = ascmhl;
+        self
+    }
+
+    // Only preview the files that will be copied.
+    pub fn dry_run(mut self, dry_run: bool) -> CopyJob {
+        self.dry_run = dry_run;
+        self
+    }
+
+    // Number of files that are copied and verified at the same time. Defaults to 1.
+    // With more than one job the output of every file is printed once the file is done.
+    pub fn jobs(mut self, jobs: usize) -> CopyJob {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    // Skip files that were already copied and verified according to the journal in the destination, without reading them again.
+    // Files that are not in the journal are copied again, even if they already exist.
+    pub fn resume(mut self, resume: bool) -> CopyJob {
+        self.resume = resume;
+        self
+    }
+
+    // Stop at the first file that fails instead of going on with the others. Files that are in progress in other jobs are finished.
+    // The mhl files and reports are still written for the files that were copied.
+    pub fn fail_fast(mut self, fail_fast: bool) -> CopyJob {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    // Start even if the pre-flight check finds that the destinations can not hold the source, e.g. not enough free space.
+    // The problems are still printed as warnings.
+    pub fn force(mut self, force: bool) -> CopyJob {
+        self.force = force;
+        self
+    }
+
+    // Sync every copied file and its directory to the disk before it is verified and declared as copied.
+    // Defaults to on if a checksum method is given.
+    pub fn fsync(mut self, fsync: bool) -> CopyJob {
+        self.fsync = Some(fsync);
+        self
+    }
+
+    // Drop every copied file from the page cache before it is verified, so the verification reads the data from the disk.
+    // Only supported on Linux. Implies syncing the files.
+    pub fn drop_cache(mut self, drop_cache: bool) -> CopyJob {
+        self.drop_cache = drop_cache;
+        self
+    }
+
+    // Read the destination files with direct I/O when verifying them, so the checksum is calculated from the data on the disk
+    // and not from the page cache. Falls back to normal reads with a warning if the file system does not support it.
+    pub fn verify_direct(mut self, verify_direct: bool) -> CopyJob {
+        self.verify_direct = verify_direct;
+        self
+    }
+
+    // Skip files that already exist in the destination with the same size and modification date without reading them.
+    // Otherwise existing files are verified, using the checksums of earlier mhl files in the destination instead of reading the destination file if possible.
+    pub fn trust_size_and_mtime(mut self, trust_size_and_mtime: bool) -> CopyJob {
+        self.trust_size_and_mtime = trust_size_and_mtime;
+        self
+    }
+
+    // Adds a glob pattern of files and directories that are not copied, e.g. "*.tmp" or "CACHE/". Can be called multiple times.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> CopyJob {
+        self.excludes.push(pattern.into());
+        self
+    }
+
+    // Adds a glob pattern of files that are copied even if an exclude pattern or the built-in list matches them. Can be called multiple times.
+    pub fn include(mut self, pattern: impl Into<String>) -> CopyJob {
+        self.includes.push(pattern.into());
+        self
+    }
+
+    // Skip the macOS system files (.DS_Store, .Spotlight-V100, ._* and so on). Defaults to on.
+    pub fn default_excludes(mut self, default_excludes: bool) -> CopyJob {
+        self.default_excludes = default_excludes;
+        self
+    }
+
+    // How symbolic links in the source are handled. Defaults to following them.
+    pub fn symlinks(mut self, symlinks: SymlinkMode) -> CopyJob {
+        self.symlinks = symlinks;
+        self
+    }
+
+    // How named pipes, sockets and devices in the source are handled. Defaults to skipping them.
+    pub fn special_files(mut self, special_files: SpecialFileMode) -> CopyJob {
+        self.special_files = special_files;
+        self
+    }
+
+    // Copy the extended attributes of the files, e.g. Finder metadata on macOS or user attributes on Linux. Defaults to off.
+    pub fn xattrs(mut self, xattrs: bool) -> CopyJob {
+        self.attributes.xattrs = xattrs;
+        self
+    }
+
+    // Copy the ACLs of the files. Defaults to off.
+    pub fn acls(mut self, acls: bool) -> CopyJob {
+        self.attributes.acls = acls;
+        self
+    }
+
+    // Give the copies the owner of the source files. Usually requires root. Defaults to off, the copies belong to the user running the job.
+    pub fn owner(mut self, owner: bool) -> CopyJob {
+        self.owner = owner;
+        self
+    }
+
+    // Give the copies the group of the source files. Defaults to off.
+    pub fn group(mut self, group: bool) -> CopyJob {
+        self.group = group;
+        self
+    }
+
+    // Give all copies this owner, by name or id, instead of the one of the source.
+    pub fn map_user(mut self, user: impl Into<String>) -> CopyJob {
+        self.map_user = Some(user.into());
+        self
+    }
+
+    // Give all copies this group, by name or id, instead of the one of the source.
+    pub fn map_group(mut self, group: impl Into<String>) -> CopyJob {
+        self.map_group = Some(group.into());
+        self
+    }
+
+    // How the progress is shown. With ProgressMode::Json JSON events are sent besides the human readable lines, without transfer speeds.
+    pub fn progress(mut self, progress: ProgressMode) -> CopyJob {
+        self.progress = progress;
+        self
+    }
+
+    // Receives the output of the job: the human readable lines, warnings and errors, transfer speeds and JSON events.
+    // Nothing is printed without it. With several jobs it is called from several threads.
+    pub fn on_event(mut self, on_event: impl Fn(Event) + Send + Sync + 'static) -> CopyJob {
+        self.on_event = EventCallback::new(on_event);
+        self
+    }
+
+    // Write a report of every file with its status, checksums, duration and errors once the job is done.
+    pub fn report(mut self, format: ReportFormat, path: impl Into<PathBuf>) -> CopyJob {
+        self.report = Some((format, path.into()));
+        self
+    }
+
+    // Write a printable HTML report of the job to each destination directory, next to the mhl file.
+    pub fn html_report(mut self, html_report: bool) -> CopyJob {
+        self.html_report = html_report;
+        self
+    }
+
+    // Looks up the user and group to map the copies to.
+    fn ownership(&self) -> Result<Ownership, Error> {
+        let uid = self.map_user.as_ref()
+            .map(|name| user_id(name).ok_or_else(|| Error::UnknownUser(name.clone())))
+            .transpose()?;
+        let gid = self.map_group.as_ref()
+            .map(|name| group_id(name).ok_or_else(|| Error::UnknownGroup(name.clone())))
+            .transpose()?;
+
+        Ok(Ownership { owner: self.owner, group: self.group, uid, gid })
+    }
+
+    // Builds the filter from the built-in list, the ignore file in the source root and the patterns of the job.
+    fn filter(&self) -> Result<Filter, Error> {
+        let mut filter = Filter::new(self.default_excludes);
+
+        let ignore_file = self.input.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            filter.read_ignore_file(&ignore_file)?;
+        }
+        for pattern in &self.excludes {
+            filter.exclude(pattern)?;
+        }
+        for pattern in &self.includes {
+            filter.include(pattern)?;
+        }
+
+        Ok(filter)
+    }
+
+    // True if copied files are synced to the disk.
+    fn syncs_files(&self) -> bool {
+        self.drop_cache || self.fsync.unwrap_or(!self.checksums.is_empty())
+    }
+
+    // Checks the input and destination directories and the options.
+    fn validate(&self) -> Result<(), Error> {
+        // Check if the input and destination directorys exist and are directories.
+        if !self.input.exists() {
+            return Err(Error::InputNotFound(self.input.clone()));
+        }
+        if !self.input.is_dir() {
+            return Err(Error::InputNotDirectory(self.input.clone()));
+        }
+        if self.destinations.is_empty() {
+            return Err(Error::NoDestination);
+        }
+
+        for (i, destination) in self.destinations.iter().enumerate() {
+            if !destination.exists() {
+                return Err(Error::DestinationNotFound(destination.clone()));
+            }
+            if !destination.is_dir() {
+                return Err(Error::DestinationNotDirectory(destination.clone()));
+            }
+            if self.input == *destination {
+                return Err(Error::SameInputAndDestination(destination.clone()));
+            }
+            if self.destinations[..i].contains(destination) {
+                return Err(Error::DuplicateDestination(destination.clone()));
+            }
+        }
+
+        // ASC MHL needs a checksum for every file.
+        if self.ascmhl && self.checksums.is_empty() {
+            return Err(Error::ChecksumRequired);
+        }
+        if self.ascmhl {
+            if let Some(method) = self.checksums.iter().find(|method| method.ascmhl_name().is_none()) {
+                return Err(Error::ChecksumNotInAscMhl(*method));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs the copy job. Files that fail are listed in the result, only problems with the job itself are returned as error.
+    pub fn run(&self) -> Result<CopyResult, Error> {
+        self.validate()?;
+        let filter = self.filter()?;
+        let ownership = self.ownership()?;
+
+        let started = Instant::now();
+        let start_date = format_system_time_to_rfc3339(SystemTime::now());
+        let start_date_for_file_name: String = start_date.replace(':', "").replace('T', "_").replace('Z', "");
+
+        let input_parent = self.input.parent().unwrap_or(Path::new(""));
+        let input_name = self.input.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        // Name of the mhl file and the HTML report, without extension.
+        let report_name = format!("{}_{}", input_name, start_date_for_file_name);
+
+        // Search the input directory recursively for files and empty directories. Excluded directories are skipped as a whole.
+        let scan = scan_directory(&self.input, &filter, self.symlinks).map_err(|source| Error::Io { path: self.input.clone(), source })?;
+        let mut files = scan.files;
+        files.sort();
+        let mut excluded = scan.excluded;
+        excluded.sort();
+        let mut excluded_dirs = scan.excluded_dirs;
+        excluded_dirs.sort();
+        let dirs = scan.dirs;
+        let mut links = scan.links;
+        links.sort();
+        let mut skipped_links = scan.skipped_links;
+        skipped_links.sort();
+        let mut unreadable = scan.unreadable;
+        unreadable.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Special files are only recreated if asked for and possible, the others are skipped.
+        let (mut special_files, mut skipped_special_files): (Vec<_>, Vec<_>) = scan.special_files.into_iter()
+            .partition(|(_, kind)| self.special_files == SpecialFileMode::Recreate && kind.can_recreate());
+        special_files.sort_by(|a, b| a.0.cmp(&b.0));
+        skipped_special_files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if self.dry_run {
+            for link in &skipped_links {
+                self.line(format_args!("Skipped symbolic link {}", link.display()));
+            }
+            for (file, kind) in &skipped_special_files {
+                self.line(format_args!("Skipped {} {}", kind, file.display()));
+            }
+            for dir in &excluded_dirs {
+                self.line(format_args!("Excluded directory {}", dir.display()));
+            }
+            for file in &excluded {
+                self.line(format_args!("Excluded {}", file.display()));
+            }
+        }
+
+        // Check the free space and the limits of the destinations before anything is written. A dry run only lists the problems.
+        let sizes: Vec<(PathBuf, u64)> = files.iter().map(|file| (file.clone(), file.metadata().map(|metadata| metadata.len()).unwrap_or_default())).collect();
+        let paths: Vec<PathBuf> = files.iter().chain(&dirs).chain(&links).chain(special_files.iter().map(|(file, _)| file)).cloned().collect();
+        let issues = preflight_check(&sizes, &paths, input_parent, &self.destinations, self.dry_run);
+        for issue in &issues {
+            self.error_line(format!("Warning: {}", issue));
+        }
+        if !issues.is_empty() && !self.force && !self.dry_run {
+            return Err(Error::Preflight(issues));
+        }
+
+        // Initialze some stuff
+        let total_files = files.len();
+        let mut result = CopyResult {
+            destinations: self.destinations.iter().map(|path| DestinationResult {
+                path: path.clone(),
+                copied: Vec::new(),
+                skipped: Vec::new(),
+                failed: Vec::new(),
+                links: Vec::new(),
+                special_files: Vec::new(),
+                metadata_failures: Vec::new(),
+                records: Vec::new(),
+                hashes: Vec::new(),
+                mhl_file: None,
+                ascmhl_generation: None,
+                html_report: None,
+            }).collect(),
+            excluded,
+            excluded_dirs,
+            skipped_links,
+            skipped_special_files,
+            start_date: start_date.clone(),
+            duration: Duration::ZERO,
+            dry_run: self.dry_run,
+            stopped: false,
+            report_error: None,
+        };
+
+        // Files and directories that could not be read fail in every destination. The rest of the source is still copied.
+        for (path, e) in &unreadable {
+            let relative_path = path.strip_prefix(input_parent).unwrap_or(path);
+            let error = format!("Could not read {}: {}", path.display(), e);
+            self.error_line(format!("Error: {}", error));
+            for destination in &mut result.destinations {
+                let destination_path = destination.path.join(relative_path);
+                self.record_failure(destination, path, destination_path, FailureKind::Source, error.clone());
+            }
+        }
+        result.stopped = self.fail_fast && !unreadable.is_empty();
+
+        // Directories of an earlier copy that were made writable, with their permissions before.
+        let mut writable_dirs: Vec<(PathBuf, fs::Permissions)> = Vec::new();
+
+        // Remove temporary files left over by an interrupted copy. During a dry run they are only listed.
+        for destination in &self.destinations {
+            let copy_root = destination.join(&input_name);
+            if !copy_root.is_dir() {
+                continue;
+            }
+            // An earlier run gave the copied directories the permissions of the source, which may be read-only.
+            // They get them again once everything is written. Other directories in the destination are left alone.
+            if !self.dry_run {
+                let copy_dirs: Vec<PathBuf> = dirs.iter().map(|dir| destination.join(dir.strip_prefix(input_parent).unwrap_or(dir))).collect();
+                if let Err(e) = make_dirs_writable(&copy_dirs, &mut writable_dirs) {
+                    self.error_line(format!("Warning: Could not make the directories in {} writable: {}", copy_root.display(), e));
+                }
+            }
+            // Links and special files are recreated through temporary entries as well. The scan does not follow links, so only the temporary entries themselves are removed.
+            let leftovers = scan_directory(&copy_root, &Filter::new(false), SymlinkMode::CopyAsLink).map_err(|source| Error::Io { path: copy_root.clone(), source })?;
+            let temp_files = leftovers.files.iter()
+                .chain(&leftovers.links)
+                .chain(leftovers.special_files.iter().map(|(path, _)| path))
+                .filter(|path| is_temp_file(path));
+            for temp_file in temp_files {
+                if self.dry_run {
+                    self.line(format_args!("Found leftover temporary file {}", temp_file.display()));
+                    continue;
+                }
+                match fs::remove_file(temp_file) {
+                    Ok(()) => self.line(format_args!("Removed leftover temporary file {}", temp_file.display())),
+                    Err(e) => self.error_line(format!("Error: Could not remove leftover temporary file {}: {}", temp_file.display(), e)),
+                }
+            }
+        }
+
+        // Open the journals of finished files. They are only read during a dry run.
+        let journals = self.destinations.iter()
+            .map(|destination| Journal::open(destination, !self.dry_run).map_err(|source| Error::Io { path: destination.clone(), source }))
+            .collect::<Result<Vec<Journal>, Error>>()?;
+
+        // Read the checksums recorded in earlier mhl files. They are only used to check existing files, which resume does not do.
+        let recorded: Vec<HashMap<String, MhlEntry>> = self.destinations.iter()
+            .map(|destination| if self.resume { HashMap::new() } else { recorded_checksums(destination, &self.on_event) })
+            .collect();
+
+        let context = FileContext {
+            total_files,
+            input_parent,
+            buffered: self.jobs > 1,
+            show_speed: self.jobs == 1 && self.progress == ProgressMode::Text,
+            journals: &journals,
+            recorded: &recorded,
+            direct_io_warned: self.destinations.iter().map(|_| AtomicBool::new(false)).collect(),
+            ownership,
+        };
+
+        // Copy the files. Workers take the next file from the list, the results are collected in the order of the files.
+        let next_file = AtomicUsize::new(0);
+        // Set with fail_fast once a file failed. The workers finish their current file and take no new ones.
+        let stop = AtomicBool::new(result.stopped);
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+
+            for _ in 0..self.jobs.min(total_files) {
+                let sender = sender.clone();
+                let next_file = &next_file;
+                let files = &files;
+                let context = &context;
+                let stop = &stop;
+                scope.spawn(move || loop {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let index = next_file.fetch_add(1, Ordering::SeqCst);
+                    if index >= total_files {
+                        break;
+                    }
+                    let file_started = Instant::now();
+                    let mut outcome = self.process_file(index, &files[index], context);
+                    if !self.dry_run {
+                        self.record_finished(&files[index], &mut outcome, context);
+                    }
+                    if self.progress == ProgressMode::Json {
+                        self.send_file_events(&files[index], &outcome, context);
+                    }
+                    if self.fail_fast && outcome.destinations.iter().any(|(_, status)| matches!(status, FileStatus::Failed(..))) {
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                    if sender.send((index, outcome, file_started.elapsed())).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+
+            let mut finished: BTreeMap<usize, (FileOutcome, Duration)> = BTreeMap::new();
+            let mut next_to_collect = 0;
+
+            for (index, outcome, duration) in receiver {
+                finished.insert(index, (outcome, duration));
+
+                while let Some((outcome, duration)) = finished.remove(&next_to_collect) {
+                    outcome.log.flush();
+                    let file = &files[next_to_collect];
+                    let relative_path = file.strip_prefix(input_parent).unwrap_or(file);
+                    let size = file.metadata().map(|metadata| metadata.len()).unwrap_or_default();
+
+                    for (dest_index, status) in outcome.destinations {
+                        let destination = &mut result.destinations[dest_index];
+                        let mut record = FileRecord {
+                            source: file.clone(),
+                            destination: destination.path.join(relative_path),
+                            size,
+                            checksums: Vec::new(),
+                            duration,
+                            status: RecordStatus::Failed,
+                            failure: None,
+                            error: None,
+                        };
+                        match status {
+                            FileStatus::Copied(hash) => {
+                                destination.copied.push(file.clone());
+                                record.status = RecordStatus::Copied;
+                                record.checksums = hash.as_ref().map(|hash| hash.checksums.clone()).unwrap_or_default();
+                                destination.hashes.extend(hash);
+                            }
+                            FileStatus::Skipped(hash) => {
+                                destination.skipped.push(file.clone());
+                                record.status = RecordStatus::Skipped;
+                                record.checksums = hash.as_ref().map(|hash| hash.checksums.clone()).unwrap_or_default();
+                                destination.hashes.extend(hash);
+                            }
+                            FileStatus::Failed(failure, error) => {
+                                destination.failed.push(file.clone());
+                                record.failure = Some(failure);
+                                record.error = Some(error);
+                            }
+                        }
+                        destination.records.push(record);
+                    }
+                    for (dest_index, failure) in outcome.metadata_failures {
+                        result.destinations[dest_index].metadata_failures.push((file.clone(), failure));
+                    }
+                    next_to_collect += 1;
+                }
+            }
+        });
+
+        if stop.load(Ordering::SeqCst) {
+            self.error_line("Error: Stopping at the first error. The remaining files are not copied.".to_string());
+            result.stopped = true;
+        }
+
+        // Recreate the symbolic links. They have no content of their own, so they are not listed in the mhl files.
+        for link in &links {
+            if result.stopped {
+                break;
+            }
+            let relative_path = link.strip_prefix(input_parent).unwrap_or(link);
+            let target = fs::read_link(link).map(|target| target.display().to_string()).unwrap_or_default();
+
+            self.line("-------------------------");
+            for destination in &mut result.destinations {
+                let destination_link = destination.path.join(relative_path);
+                self.line(format_args!("{} --> {} (symbolic link to {})", link.display(), destination_link.display(), target));
+
+                if self.dry_run {
+                    continue;
+                }
+                match copy_link(link, std::slice::from_ref(&destination_link)) {
+                    Ok(()) => {
+                        destination.links.push(link.clone());
+                        if ownership.any() {
+                            if let Err(e) = fs::symlink_metadata(link).and_then(|metadata| copy_ownership(&metadata, &destination_link, ownership)) {
+                                self.error_line(format!("Warning: Could not copy owner to {}: {}", destination_link.display(), e));
+                                destination.metadata_failures.push((link.clone(), format!("owner: {}", e)));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let error = format!("Could not create symbolic link {}: {}", destination_link.display(), e);
+                        self.error_line(format!("Error: {}", error));
+                        self.record_failure(destination, link, destination_link, FailureKind::Destination, error);
+                        result.stopped = self.fail_fast;
+                    }
+                }
+            }
+        }
+
+        // Recreate the named pipes and device nodes. Like links they have no content and are not listed in the mhl files.
+        for (file, kind) in &special_files {
+            if result.stopped {
+                break;
+            }
+            let relative_path = file.strip_prefix(input_parent).unwrap_or(file);
+
+            self.line("-------------------------");
+            for destination in &mut result.destinations {
+                let destination_file = destination.path.join(relative_path);
+                self.line(format_args!("{} --> {} ({})", file.display(), destination_file.display(), kind));
+
+                if self.dry_run {
+                    continue;
+                }
+                match recreate_special_file(file, std::slice::from_ref(&destination_file)) {
+                    Ok(()) => {
+                        destination.special_files.push(file.clone());
+                        if ownership.any() {
+                            if let Err(e) = fs::metadata(file).and_then(|metadata| copy_ownership(&metadata, &destination_file, ownership)) {
+                                self.error_line(format!("Warning: Could not copy owner to {}: {}", destination_file.display(), e));
+                                destination.metadata_failures.push((file.clone(), format!("owner: {}", e)));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let error = format!("Could not create {} {}: {}", kind, destination_file.display(), e);
+                        self.error_line(format!("Error: {}", error));
+                        self.record_failure(destination, file, destination_file, FailureKind::Destination, error);
+                        result.stopped = self.fail_fast;
+                    }
+                }
+            }
+        }
+
+        // Keep the mhl entries in a deterministic order.
+        for destination in &mut result.destinations {
+            destination.hashes.sort_by(|a, b| a.file.cmp(&b.file));
+        }
+
+        if self.dry_run {
+            self.finish(&mut result, started, &report_name);
+            return Ok(result);
+        }
+
+        if self.mhl {
+            for destination in &mut result.destinations {
+                if destination.copied.is_empty() {
+                    continue;
+                }
+
+                self.line(format_args!("-------------------------"));
+                self.line(format_args!("Writing mhl file to {}...", destination.path.display()));
+
+                // MHL file name is the basedir of the source directory + the current date and time + .mhl
+                let mhl_file = destination.path.join(format!("{}.mhl", report_name));
+
+                // A mhl file that can not be written fails this destination, the others and the reports are still written.
+                match write_mhl_v2(&mhl_file, &destination.hashes, &start_date) {
+                    Ok(()) => destination.mhl_file = Some(mhl_file),
+                    Err(e) => {
+                        let error = format!("Could not write mhl file {}: {}", mhl_file.display(), e);
+                        self.error_line(format!("Error: {}", error));
+                        // A truncated mhl file would be read again by the next run.
+                        let _ = fs::remove_file(&mhl_file);
+                        self.record_failure(destination, &self.input, mhl_file, FailureKind::Destination, error);
+                    }
+                }
+            }
+        }
+
+        if self.ascmhl {
+            // The ASC MHL history lives in the copied directory, not in the destination itself.
+            let ignore_patterns = filter.exclude_patterns();
+
+            for destination in &mut result.destinations {
+                if destination.hashes.is_empty() {
+                    continue;
+                }
+
+                let root = destination.path.join(&input_name);
+                let process = if destination.copied.is_empty() { "in-place" } else { "ingest" };
+
+                self.line(format_args!("-------------------------"));
+                self.line(format_args!("Writing ASC MHL generation to {}...", root.join(ascmhl::ASCMHL_FOLDER).display()));
+
+                match ascmhl::write_ascmhl_generation(&root, &destination.path, &destination.hashes, process, &start_date, &ignore_patterns) {
+                    Ok(generation) => destination.ascmhl_generation = Some(generation),
+                    Err(e) => {
+                        let ascmhl_folder = root.join(ascmhl::ASCMHL_FOLDER);
+                        let error = format!("Could not write ASC MHL generation to {}: {}", ascmhl_folder.display(), e);
+                        self.error_line(format!("Error: {}", error));
+                        self.record_failure(destination, &self.input, ascmhl_folder, FailureKind::Destination, error);
+                    }
+                }
+            }
+        }
+
+        // After a stop the directories are left as they are, only the ones needed for the copied files exist.
+        if !result.stopped {
+            self.copy_directories(&dirs, input_parent, ownership, &mut result);
+        }
+        // copy_directories did not run or stopped early, the directories that were made writable get their permissions back.
+        if result.stopped {
+            if let Err(e) = restore_dir_permissions(&writable_dirs) {
+                self.error_line(format!("Warning: Could not restore the permissions of the directories: {}", e));
+            }
+        }
+
+        self.finish(&mut result, started, &report_name);
+        Ok(result)
+    }
+
+    // Writes the reports and the job_finished event.
+    fn finish(&self, result: &mut CopyResult, started: Instant, report_name: &str) {
+        result.duration = started.elapsed();
+
+        if self.html_report && !self.dry_run {
+            for index in 0..result.destinations.len() {
+                let html_file = result.destinations[index].path.join(format!("{}.html", report_name));
+                self.line("-------------------------");
+                self.line(format_args!("Writing HTML report to {}...", html_file.display()));
+                match write_html_report(&html_file, &self.input, &self.checksums, result) {
+                    Ok(()) => result.destinations[index].html_report = Some(html_file),
+                    Err(e) => {
+                        let error = format!("Could not write HTML report {}: {}", html_file.display(), e);
+                        self.error_line(format!("Error: {}", error));
+                        let _ = fs::remove_file(&html_file);
+                        self.record_failure(&mut result.destinations[index], &self.input, html_file, FailureKind::Destination, error);
+                    }
+                }
+            }
+        }
+
+        if let Some((ReportFormat::Json, path)) = &self.report {
+            self.line("-------------------------");
+            self.line(format_args!("Writing report to {}...", path.display()));
+            if let Err(e) = write_json_report(path, &self.input, result) {
+                let error = format!("Could not write report {}: {}", path.display(), e);
+                self.error_line(format!("Error: {}", error));
+                result.report_error = Some(error);
+            }
+        }
+
+        if self.progress == ProgressMode::Json {
+            self.on_event.send(json_event("job_finished", json!({
+                "source": path_json(&self.input),
+                "dry_run": result.dry_run,
+                "success": !result.had_errors(),
+                "stopped": result.stopped,
+                "duration": result.duration.as_secs_f64(),
+                "destinations": result.destinations.iter().map(destination_totals_json).collect::<Vec<_>>(),
+                "report_error": result.report_error,
+            })));
+        }
+    }
+
+    // Sends a line of the human readable output.
+    fn line(&self, line: impl fmt::Display) {
+        self.on_event.send(Event::Line(line.to_string()));
+    }
+
+    // Sends a warning or error of the human readable output.
+    fn error_line(&self, line: String) {
+        self.on_event.send(Event::ErrorLine(line));
+    }
+
+    // Sends the file_verified and file_failed events of a processed file.
+    fn send_file_events(&self, file: &Path, outcome: &FileOutcome, context: &FileContext) {
+        let relative_path = file.strip_prefix(context.input_parent).unwrap_or(file);
+
+        for (dest_index, status) in &outcome.destinations {
+            let destination = path_json(&self.destinations[*dest_index].join(relative_path));
+            match status {
+                FileStatus::Copied(hash) | FileStatus::Skipped(hash) => self.on_event.send(json_event("file_verified", json!({
+                    "source": path_json(file),
+                    "destination": destination,
+                    "status": if matches!(status, FileStatus::Copied(_)) { "copied" } else { "skipped" },
+                    "verified": hash.as_ref().is_some_and(|hash| !hash.checksums.is_empty()),
+                    "checksums": checksums_json(hash.as_ref().map(|hash| &hash.checksums[..]).unwrap_or_default()),
+                }))),
+                FileStatus::Failed(failure, error) => self.on_event.send(json_event("file_failed", json!({
+                    "source": path_json(file),
+                    "destination": destination,
+                    "failure": failure.name(),
+                    "error": error,
+                }))),
+            }
+        }
+    }
+
+    // Creates the directories of the source in the destinations, including empty ones, and copies their permissions, dates and metadata.
+    // This runs once all files, links and mhl histories are written and goes deepest first: creating a subdirectory changes the
+    // modification date of its parent, and read-only directories would stop anything from being written into them.
+    // The next run makes them writable for their owner again before it copies anything, see make_dirs_writable.
+    fn copy_directories(&self, dirs: &[PathBuf], input_parent: &Path, ownership: Ownership, result: &mut CopyResult) {
+        let mut dirs = dirs.to_vec();
+        dirs.sort_by(|a, b| b.cmp(a));
+
+        for dir in &dirs {
+            if result.stopped {
+                break;
+            }
+            let relative_dir = dir.strip_prefix(input_parent).unwrap_or(dir);
+            let metadata = match fs::metadata(dir) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    let error = format!("Could not read {}: {}", dir.display(), e);
+                    self.error_line(format!("Error: {}", error));
+                    for destination in &mut result.destinations {
+                        let destination_dir = destination.path.join(relative_dir);
+                        self.record_failure(destination, dir, destination_dir, FailureKind::Source, error.clone());
+                    }
+                    result.stopped = self.fail_fast;
+                    continue;
+                }
+            };
+
+            for destination in &mut result.destinations {
+                let destination_dir = destination.path.join(relative_dir);
+                if !destination_dir.exists() {
+                    if let Err(e) = fs::create_dir_all(&destination_dir) {
+                        let error = format!("Could not create directory {}: {}", destination_dir.display(), e);
+                        self.error_line(format!("Error: {}", error));
+                        self.record_failure(destination, dir, destination_dir, FailureKind::Destination, error);
+                        result.stopped = self.fail_fast;
+                        continue;
+                    }
+                }
+
+                let mut failures: Vec<(String, std::io::Error)> = Vec::new();
+                if ownership.any() {
+                    if let Err(e) = copy_ownership(&metadata, &destination_dir, ownership) {
+                        failures.push(("owner".to_string(), e));
+                    }
+                }
+                if self.attributes.any() {
+                    failures.extend(copy_attributes(dir, &destination_dir, self.attributes));
+                }
+                if let Err(e) = copy_dir_times(dir, &destination_dir) {
+                    failures.push(("dates".to_string(), e));
+                }
+                // The permissions come last, the directory may not be writable afterwards.
+                if let Err(e) = fs::set_permissions(&destination_dir, metadata.permissions()) {
+                    failures.push(("permissions".to_string(), e));
+                }
+
+                for (name, e) in failures {
+                    self.error_line(format!("Warning: Could not copy {} to {}: {}", name, destination_dir.display(), e));
+                    destination.metadata_failures.push((dir.clone(), format!("{}: {}", name, e)));
+                }
+            }
+        }
+    }
+
+    // Records a source entry that failed outside of the file copy, e.g. an unreadable directory or a link that could not be created.
+    // A mhl file or ASC MHL generation that could not be written is recorded with the input directory as source.
+    fn record_failure(&self, destination: &mut DestinationResult, source: &Path, destination_path: PathBuf, failure: FailureKind, error: String) {
+        if self.progress == ProgressMode::Json {
+            self.on_event.send(json_event("file_failed", json!({
+                "source": path_json(source),
+                "destination": path_json(&destination_path),
+                "failure": failure.name(),
+                "error": error,
+            })));
+        }
+        destination.failed.push(source.to_path_buf());
+        destination.records.push(FileRecord {
+            source: source.to_path_buf(),
+            destination: destination_path,
+            size: 0,
+            checksums: Vec::new(),
+            duration: Duration::ZERO,
+            status: RecordStatus::Failed,
+            failure: Some(failure),
+            error: Some(error),
+        });
+    }
+}
+
+// Output of a single file. Sent directly when files are copied one at a time,
+// otherwise buffered and sent once the file is done so the output of parallel copies does not mix.
+struct FileLog {
+    buffered: bool,
+    on_event: EventCallback,
+    events: Vec<Event>,
+}
+
+impl FileLog {
+    fn new(buffered: bool, on_event: EventCallback) -> FileLog {
+        FileLog { buffered, on_event, events: Vec::new() }
+    }
+
+    fn line(&mut self, line: String) {
+        self.send(Event::Line(line));
+    }
+
+    fn error_line(&mut self, line: String) {
+        self.send(Event::ErrorLine(line));
+    }
+
+    fn send(&mut self, event: Event) {
+        if self.buffered {
+            self.events.push(event);
+        } else {
+            self.on_event.send(event);
+        }
+    }
+
+    fn flush(self) {
+        for event in self.events {
+            self.on_event.send(event);
+        }
+    }
+}
+
+// Status of a file in a single destination.
+enum FileStatus {
+    // Copied, with the entry for the mhl file if a checksum method was given.
+    Copied(Option<FileMetadata>),
+    // Already existed and was verified to be identical, or was finished according to the journal.
+    Skipped(Option<FileMetadata>),
+    // Could not be copied or verified, with the reason and the error.
+    Failed(FailureKind, String),
+}
+
+// Shared state of the workers copying the files.
+struct FileContext<'a> {
+    total_files: usize,
+    input_parent: &'a Path,
+    // Print the output of a file once it is done instead of directly.
+    buffered: bool,
+    // Show the transfer speed while copying and verifying. Off with parallel jobs and JSON events.
+    show_speed: bool,
+    // Journal of every destination, in the same order as the destinations.
+    journals: &'a [Journal],
+    // Checksums recorded in earlier mhl files of every destination, by path relative to the destination.
+    recorded: &'a [HashMap<String, MhlEntry>],
+    // Whether the warning about missing direct I/O support was printed, per destination.
+    direct_io_warned: Vec<AtomicBool>,
+    // Owner and group of the copies.
+    ownership: Ownership,
+}
+
+// Result of copying a single file to all destinations.
+struct FileOutcome {
+    log: FileLog,
+    destinations: Vec<(usize, FileStatus)>,
+    // Metadata that could not be copied, per destination.
+    metadata_failures: Vec<(usize, String)>,
+}
+
+impl CopyJob {
+    // Copies a single file to all destinations and verifies the copies.
+    fn process_file(&self, index: usize, file: &Path, context: &FileContext) -> FileOutcome {
+        let FileContext { total_files, input_parent, buffered, journals, .. } = *context;
+        let mut log = FileLog::new(buffered, self.on_event.clone());
+        let mut statuses: Vec<(usize, FileStatus)> = Vec::new();
+        let mut metadata_failures: Vec<(usize, String)> = Vec::new();
+        let checksums = &self.checksums[..];
+
+        // Path of the file relative to the parent of the input directory.
+        let relative_path = file.strip_prefix(input_parent).unwrap_or(file);
+        let journal_path = relative_path.to_string_lossy();
+
+        // The metadata of the file that is copied, of the target if the file was reached through a symbolic link.
+        let source_metadata = match file.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                let error = format!("Could not read {}: {}", file.display(), e);
+                log.error_line(format!("Error: {}", error));
+                let destinations = (0..self.destinations.len()).map(|i| (i, FileStatus::Failed(FailureKind::Source, error.clone()))).collect();
+                return FileOutcome { log, destinations, metadata_failures: Vec::new() };
+            }
+        };
+        let source_size = source_metadata.len();
+        let source_modified = source_metadata.modified().ok().and_then(journal_time);
+
+        if self.progress == ProgressMode::Json {
+            self.on_event.send(json_event("file_started", json!({ "index": index + 1, "total_files": total_files, "source": path_json(file), "size": source_size })));
+        }
+
+        // The source checksums are only calculated once, even if the file already exists in several destinations.
+        let mut src_checksums: Option<Vec<Checksum>> = None;
+
+        // Destinations the file still has to be copied to.
+        let mut pending: Vec<usize> = Vec::new();
+
+        for (dest_index, destination) in self.destinations.iter().enumerate() {
+
+            // Destination file
+            let destination_file = destination.join(relative_path);
+
+            // With resume, files in the journal are skipped without reading them. All other files are unfinished and copied again.
+            if self.resume {
+                let finished = journals[dest_index].get(&journal_path)
+                    .and_then(|entry| finished_checksums(entry, source_size, source_modified, checksums, &destination_file));
+                if let Some(finished) = finished {
+                    log.line("-------------------------".to_string());
+                    log.line(format!("{} / {}: File {} was already copied and verified. Skipping.", index + 1, total_files, destination_file.display()));
+                    let hash = Some(finished).filter(|finished| !finished.is_empty())
+                        .map(|finished| file_metadata(file, &destination_file, destination, &finished, true));
+                    statuses.push((dest_index, FileStatus::Skipped(hash)));
+                    continue;
+                }
+                pending.push(dest_index);
+                continue;
+            }
+
+            // Check if the file already exists in the destination directory. Verify that the file sizes match and the checksums match.
+            let existing = destination_file.metadata().ok();
+            if existing.as_ref().is_some_and(|metadata| metadata.len() == source_size) {
+                let destination_modified = existing.and_then(|metadata| metadata.modified().ok());
+                log.line("-------------------------".to_string());
+
+                // With trust_size_and_mtime an identical size and modification date is enough, the file is not read at all.
+                if self.trust_size_and_mtime && source_modified.is_some() && destination_modified.and_then(journal_time) == source_modified {
+                    log.line(format!("{} / {}: File {} already exists with identical file size and modification date. Skipping.", index + 1, total_files, destination_file.display()));
+                    let hash = context.recorded[dest_index].get(journal_path.as_ref())
+                        .and_then(|entry| recorded_checksums_for(entry, checksums))
+                        .map(|recorded| file_metadata(file, &destination_file, destination, &recorded, true));
+                    statuses.push((dest_index, FileStatus::Skipped(hash)));
+                    continue;
+                }
+
+                // Checksums of the destination file recorded in an earlier mhl file. They are only used if the file was not changed since.
+                let recorded = context.recorded[dest_index].get(journal_path.as_ref())
+                    .filter(|entry| entry.size == source_size && entry.last_modification_date.is_some()
+                        && entry.last_modification_date == destination_modified.map(format_system_time_to_rfc3339))
+                    .map(MhlEntry::known_checksums)
+                    .filter(|recorded| recorded.iter().any(|checksum| checksums.contains(&checksum.method)));
+
+                if recorded.is_some() {
+                    log.line(format!("{} / {}: File {} already exists and has identical file size. Verifying the source against the checksums in the mhl file...", index + 1, total_files, destination_file.display()));
+                } else {
+                    log.line(format!("{} / {}: File {} already exists and has identical file size. Verifying checksums...", index + 1, total_files, destination_file.display()));
+                }
+
+                if self.dry_run {
+                    continue;
+                }
+
+                // Without a checksum the file can not be verified, so it is copied again.
+                if !checksums.is_empty() {
+                    if src_checksums.is_none() {
+                        match self.checksum_of(file, context) {
+                            Ok(values) => src_checksums = Some(values),
+                            Err(e) => {
+                                let error = format!("Could not verify checksum: {}", e);
+                                log.error_line(format!("Error: {}", error));
+                                statuses.push((dest_index, FileStatus::Failed(FailureKind::Source, error)));
+                                continue;
+                            }
+                        }
+                    }
+                    let src_checksums = src_checksums.as_deref().unwrap_or_default();
+
+                    // The destination only has to be read if there are no recorded checksums.
+                    let dest_checksums = match recorded {
+                        Some(recorded) => Ok(recorded),
+                        None => self.destination_checksum_of(&destination_file, dest_index, context, &mut log),
+                    };
+
+                    match dest_checksums {
+                        Err(e) => {
+                            let error = format!("Could not verify checksum: {}", e);
+                            log.error_line(format!("Error: {}", error));
+                            statuses.push((dest_index, FileStatus::Failed(FailureKind::Destination, error)));
+                        }
+                        Ok(dest_checksums) if checksums_match(src_checksums, &dest_checksums) => {
+                            log.line(format!("Checksums match: {}", format_checksums(src_checksums)));
+                            statuses.push((dest_index, FileStatus::Skipped(Some(file_metadata(file, &destination_file, destination, src_checksums, true)))));
+                        }
+                        Ok(dest_checksums) => {
+                            log.line("Error: Checksums do not match. File was not copied successfully.".to_string());
+                            let error = format!("Checksums do not match. Expected {}, got {}.", format_checksums(src_checksums), format_checksums(&dest_checksums));
+                            statuses.push((dest_index, FileStatus::Failed(FailureKind::Mismatch, error)));
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            pending.push(dest_index);
+        }
+
+        if pending.is_empty() {
+            return FileOutcome { log, destinations: statuses, metadata_failures };
+        }
+
+        let destination_files: Vec<PathBuf> = pending.iter().map(|&i| self.destinations[i].join(relative_path)).collect();
+
+        log.line("-------------------------".to_string());
+        for destination_file in &destination_files {
+            log.line(format!("{} / {}: {} --> {}", index + 1, total_files, file.display(), destination_file.display()));
+        }
+
+        if self.dry_run {
+            if self.attributes.any() {
+                match list_attributes(file, self.attributes) {
+                    Ok(names) if names.is_empty() => {}
+                    Ok(names) => log.line(format!("Preserving {}", names.join(", "))),
+                    Err(e) => log.error_line(format!("Warning: Could not read the extended attributes of {}: {}", file.display(), e)),
+                }
+            }
+            return FileOutcome { log, destinations: statuses, metadata_failures };
+        }
+
+        // The data is written to temporary files next to the destination files, which are only renamed once they are verified.
+        let temp_files: Vec<PathBuf> = destination_files.iter().map(|destination_file| temp_path(destination_file)).collect();
+
+        let copy_result = if self.progress == ProgressMode::Json {
+            copy_file(file, &temp_files, checksums, &mut progress_events(file, "copy", &self.on_event))
+        } else if context.show_speed {
+            let send = |event| self.on_event.send(event);
+            let mut speed_line = SpeedLine::new("Transfer speed:", &send);
+            copy_file(file, &temp_files, checksums, &mut |bytes| speed_line.update(bytes))
+        } else {
+            copy_file(file, &temp_files, checksums, &mut |_| {})
+        };
+
+        // Errors reading the source fail every destination, errors writing a destination only fail that one.
+        let (src_checksums, write_results) = match copy_result {
+            Ok(copied) => copied,
+            Err(e) => {
+                let error = format!("Could not copy file: {}", e);
+                log.error_line(format!("Error: {}", error));
+                for temp_file in &temp_files {
+                    let _ = fs::remove_file(temp_file);
+                }
+                let failure = if is_source_error(&e) { FailureKind::Source } else { FailureKind::Destination };
+                statuses.extend(pending.iter().map(|&i| (i, FileStatus::Failed(failure, error.clone()))));
+                return FileOutcome { log, destinations: statuses, metadata_failures };
+            }
+        };
+
+        for (((&i, destination_file), temp_file), write_result) in pending.iter().zip(&destination_files).zip(&temp_files).zip(write_results) {
+            if let Err(e) = write_result {
+                let error = format!("Could not copy file to {}: {}", destination_file.display(), e);
+                log.error_line(format!("Error: {}", error));
+                let _ = fs::remove_file(temp_file);
+                statuses.push((i, FileStatus::Failed(FailureKind::Destination, error)));
+                continue;
+            }
+
+            // Missing metadata is reported, but the file itself is still copied.
+            if context.ownership.any() {
+                if let Err(e) = copy_ownership(&source_metadata, temp_file, context.ownership) {
+                    log.error_line(format!("Warning: Could not copy owner to {}: {}", destination_file.display(), e));
+                    metadata_failures.push((i, format!("owner: {}", e)));
+                }
+            }
+            if self.attributes.any() {
+                for (name, e) in copy_attributes(file, temp_file, self.attributes) {
+                    log.error_line(format!("Warning: Could not copy {} to {}: {}", name, destination_file.display(), e));
+                    metadata_failures.push((i, format!("{}: {}", name, e)));
+                }
+            }
+
+            // Make sure the data is on the disk, and optionally not in the cache anymore, before it is verified.
+            if self.syncs_files() {
+                if let Err(e) = sync_file(temp_file) {
+                    let error = format!("Could not sync {} to the disk: {}", destination_file.display(), e);
+                    log.error_line(format!("Error: {}", error));
+                    let _ = fs::remove_file(temp_file);
+                    statuses.push((i, FileStatus::Failed(FailureKind::Destination, error)));
+                    continue;
+                }
+            }
+            if self.drop_cache {
+                if let Err(e) = drop_from_cache(temp_file) {
+                    log.error_line(format!("Warning: Could not drop {} from the page cache: {}", destination_file.display(), e));
+                }
+            }
+
+            // Verify every destination on its own.
+            let hash = if src_checksums.is_empty() {
+                Ok(None)
+            } else {
+                match self.destination_checksum_of(temp_file, i, context, &mut log) {
+                    Err(e) => {
+                        let error = format!("Could not verify checksum of {}: {}", destination_file.display(), e);
+                        log.error_line(format!("Error: {}", error));
+                        Err((FailureKind::Destination, error))
+                    }
+                    Ok(dest_checksums) if src_checksums == dest_checksums => {
+                        log.line(format!("Checksums match: {} {}", format_checksums(&src_checksums), destination_file.display()));
+                        Ok(Some(file_metadata(file, destination_file, &self.destinations[i], &src_checksums, false)))
+                    }
+                    Ok(dest_checksums) => {
+                        log.line(format!("Error: Checksums do not match. File {} was not copied successfully. Expected {}, got {}.", destination_file.display(), format_checksums(&src_checksums), format_checksums(&dest_checksums)));
+                        Err((FailureKind::Mismatch, format!("Checksums do not match. Expected {}, got {}.", format_checksums(&src_checksums), format_checksums(&dest_checksums))))
+                    }
+                }
+            };
+
+            let hash = match hash {
+                Ok(hash) => hash,
+                Err((failure, error)) => {
+                    let _ = fs::remove_file(temp_file);
+                    statuses.push((i, FileStatus::Failed(failure, error)));
+                    continue;
+                }
+            };
+
+            let renamed = fs::rename(temp_file, destination_file).and_then(|()| {
+                match destination_file.parent() {
+                    Some(parent) if self.syncs_files() => sync_dir(parent),
+                    _ => Ok(()),
+                }
+            });
+
+            match renamed {
+                Ok(()) => statuses.push((i, FileStatus::Copied(hash))),
+                Err(e) => {
+                    let error = format!("Could not move {} into place: {}", destination_file.display(), e);
+                    log.error_line(format!("Error: {}", error));
+                    let _ = fs::remove_file(temp_file);
+                    statuses.push((i, FileStatus::Failed(FailureKind::Destination, error)));
+                }
+            }
+        }
+
+        if src_checksums.is_empty() && context.show_speed {
+            self.on_event.send(Event::SpeedDone { keep: true });
+        }
+
+        FileOutcome { log, destinations: statuses, metadata_failures }
+    }
+
+    // Adds the files that were copied or verified successfully to the journals.
+    fn record_finished(&self, file: &Path, outcome: &mut FileOutcome, context: &FileContext) {
+        let Ok(metadata) = file.metadata() else {
+            return;
+        };
+        let Some(modified) = metadata.modified().ok().and_then(journal_time) else {
+            return;
+        };
+        let relative_path = file.strip_prefix(context.input_parent).unwrap_or(file);
+        let journal_path = relative_path.to_string_lossy();
+
+        for (dest_index, status) in &outcome.destinations {
+            let hash = match status {
+                FileStatus::Copied(hash) | FileStatus::Skipped(hash) => hash,
+                FileStatus::Failed(..) => continue,
+            };
+            let entry = JournalEntry {
+                size: metadata.len(),
+                modified,
+                checksums: hash.as_ref().map(|hash| hash.checksums.clone()).unwrap_or_default(),
+            };
+            // An entry with more checksums, e.g. from an earlier run with more checksum methods, is kept.
+            let journal = &context.journals[*dest_index];
+            if journal.get(&journal_path).is_some_and(|recorded| recorded.size == entry.size
+                && recorded.modified == entry.modified
+                && entry.checksums.iter().all(|checksum| recorded.checksums.contains(checksum))) {
+                continue;
+            }
+            if let Err(e) = journal.record(&journal_path, &entry) {
+                outcome.log.error_line(format!("Error: Could not write journal of {}: {}", self.destinations[*dest_index].display(), e));
+            }
+        }
+    }
+
+    // Calculates the checksum of a file, printing the speed or the progress events.
+    fn checksum_of(&self, file: &Path, context: &FileContext) -> std::io::Result<Vec<Checksum>> {
+        if self.progress == ProgressMode::Json {
+            return process_checksum(file, &self.checksums, &mut progress_events(file, "verify", &self.on_event));
+        }
+        if !context.show_speed {
+            return process_checksum(file, &self.checksums, &mut |_| {});
+        }
+
+        let send = |event| self.on_event.send(event);
+        let mut speed_line = SpeedLine::new(format!("Verifying checksum... ({}) Speed:", self.checksum_names()), &send);
+        let result = process_checksum(file, &self.checksums, &mut |bytes| speed_line.update(bytes));
+        speed_line.clear();
+        result
+    }
+
+    // Calculates the checksum of a file in a destination. With verify_direct the file is read with direct I/O,
+    // falling back to a normal read if the file system does not support it.
+    fn destination_checksum_of(&self, file: &Path, dest_index: usize, context: &FileContext, log: &mut FileLog) -> std::io::Result<Vec<Checksum>> {
+        if !self.verify_direct {
+            return self.checksum_of(file, context);
+        }
+
+        let result = if self.progress == ProgressMode::Json {
+            process_checksum_direct(file, &self.checksums, &mut progress_events(file, "verify", &self.on_event))
+        } else if context.show_speed {
+            let send = |event| self.on_event.send(event);
+            let mut speed_line = SpeedLine::new(format!("Verifying checksum from disk... ({}) Speed:", self.checksum_names()), &send);
+            let result = process_checksum_direct(file, &self.checksums, &mut |bytes| speed_line.update(bytes));
+            speed_line.clear();
+            result
+        } else {
+            process_checksum_direct(file, &self.checksums, &mut |_| {})
+        };
+
+        if let Some(value) = result? {
+            return Ok(value);
+        }
+
+        // Only warn once per destination.
+        if !context.direct_io_warned[dest_index].swap(true, Ordering::SeqCst) {
+            log.error_line(format!("Warning: Direct I/O is not supported in {}. Verifying with normal reads, the data may come from the page cache.", self.destinations[dest_index].display()));
+        }
+        self.checksum_of(file, context)
+    }
+
+    // Names of the checksum methods for the output, e.g. "md5, xxh128".
+    fn checksum_names(&self) -> String {
+        self.checksums.iter().map(ChecksumMethod::name).collect::<Vec<&str>>().join(", ")
+    }
+}
+
+// Returns the progress callback of a copy or checksum pass over a file, sending bytes_progress events.
+// Temporary files are shown under the name they get once they are verified.
+fn progress_events<'a>(file: &'a Path, phase: &'a str, on_event: &'a EventCallback) -> impl FnMut(u64) + 'a {
+    let size = file.metadata().map(|metadata| metadata.len()).unwrap_or_default();
+    let path = if is_temp_file(file) { path_json(&final_path(file)) } else { path_json(file) };
+    let mut bytes = 0;
+    move |chunk| {
+        bytes += chunk;
+        on_event.send(json_event("bytes_progress", json!({ "path": path, "phase": phase, "bytes": bytes, "size": size })));
+    }
+}
+
+// Makes the directories of an earlier copy writable for their owner, so files can be created, renamed and removed in them.
+// Directories that do not exist yet are skipped. The changed directories are added to changed with their permissions before.
+#[cfg(unix)]
+fn make_dirs_writable(dirs: &[PathBuf], changed: &mut Vec<(PathBuf, fs::Permissions)>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    for dir in dirs {
+        let metadata = match fs::symlink_metadata(dir) {
+            Ok(metadata) if metadata.is_dir() => metadata,
+            Ok(_) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let permissions = metadata.permissions();
+        if permissions.mode() & 0o200 == 0 {
+            let mut writable = permissions.clone();
+            writable.set_mode(permissions.mode() | 0o200);
+            fs::set_permissions(dir, writable)?;
+            changed.push((dir.clone(), permissions));
+        }
+    }
+
+    Ok(())
+}
+
+// Elsewhere read-only directories can still be written to.
+#[cfg(not(unix))]
+fn make_dirs_writable(_dirs: &[PathBuf], _changed: &mut Vec<(PathBuf, fs::Permissions)>) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Gives the directories made writable by make_dirs_writable their permissions back.
+// Directories whose permissions were set by copy_directories in the meantime keep them.
+#[cfg(unix)]
+fn restore_dir_permissions(dirs: &[(PathBuf, fs::Permissions)]) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    for (dir, permissions) in dirs {
+        if fs::metadata(dir)?.permissions().mode() == permissions.mode() | 0o200 {
+            fs::set_permissions(dir, permissions.clone())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restore_dir_permissions(_dirs: &[(PathBuf, fs::Permissions)]) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Checks if a journal entry still matches the source file and the destination file exists with the same size and modification date.
+// The entry needs a checksum for every checksum method, it may have more. Returns its checksums in the order of the checksum methods.
+fn finished_checksums(entry: &JournalEntry, source_size: u64, source_modified: Option<(u64, u32)>, checksums: &[ChecksumMethod], destination_file: &Path) -> Option<Vec<Checksum>> {
+    let destination_metadata = destination_file.metadata().ok()?;
+    let destination_modified = destination_metadata.modified().ok().and_then(journal_time);
+
+    let unchanged = entry.size == source_size
+        && destination_metadata.len() == source_size
+        && Some(entry.modified) == source_modified
+        && destination_modified == source_modified;
+    if !unchanged {
+        return None;
+    }
+
+    checksums.iter()
+        .map(|method| entry.checksums.iter().find(|checksum| checksum.method == *method).cloned())
+        .collect()
+}
+
+// Compares the checksums of two files. Only checksums calculated with the same method are compared, at least one has to be.
+fn checksums_match(checksums: &[Checksum], other: &[Checksum]) -> bool {
+    let mut compared = false;
+    for checksum in checksums {
+        if let Some(other) = other.iter().find(|other| other.method == checksum.method) {
+            if other.value != checksum.value {
+                return false;
+            }
+            compared = true;
+        }
+    }
+    compared
+}
+
+// Returns the checksums of a mhl entry in the order of the checksum methods, if it has all of them.
+fn recorded_checksums_for(entry: &MhlEntry, checksum_methods: &[ChecksumMethod]) -> Option<Vec<Checksum>> {
+    if checksum_methods.is_empty() {
+        return None;
+    }
+    let known = entry.known_checksums();
+    checksum_methods.iter()
+        .map(|method| known.iter().find(|checksum| checksum.method == *method).cloned())
+        .collect()
+}
+
+// Reads the checksums recorded in the mhl files directly in a destination directory, by path relative to the destination.
+// Newer mhl files replace the entries of older ones. Mhl files that can not be read are skipped with a warning.
+fn recorded_checksums(destination: &Path, on_event: &EventCallback) -> HashMap<String, MhlEntry> {
+    let mut recorded: HashMap<String, MhlEntry> = HashMap::new();
+
+    let Ok(dir_entries) = fs::read_dir(destination) else {
+        return recorded;
+    };
+    let mut mhl_files: Vec<PathBuf> = dir_entries
+        .filter_map(|dir_entry| dir_entry.ok().map(|dir_entry| dir_entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension == "mhl"))
+        .collect();
+    // The file names end with the date, so sorting them puts the newest last.
+    mhl_files.sort();
+
+    for mhl_file in mhl_files {
+        match read_mhl(&mhl_file) {
+            Ok(entries) => recorded.extend(entries.into_iter().map(|entry| (entry.file.clone(), entry))),
+            Err(e) => on_event.send(Event::ErrorLine(format!("Warning: Could not read mhl file {}: {}", mhl_file.display(), e))),
+        }
+    }
+
+    recorded
+}
+
+// Builds the MediaHashList entry for a verified file.
+fn file_metadata(source_file: &Path, destination_file: &Path, destination_dir: &Path, checksums: &[Checksum], verified: bool) -> FileMetadata {
+    let metadata = source_file.metadata().ok();
+    FileMetadata {
+        file: destination_file.strip_prefix(destination_dir).unwrap_or(destination_file).to_string_lossy().to_string(),
+        size: metadata.as_ref().map(|metadata| metadata.len()).unwrap_or_default(),
+        last_modification_date: metadata.and_then(|metadata| metadata.modified().ok()).unwrap_or_else(SystemTime::now),
+        checksums: checksums.to_vec(),
+        hash_date: SystemTime::now(),
+        verified,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascmhl_rejects_checksums_without_element() {
+        let root = std::env::temp_dir().join(format!("rccopy-job-ascmhl-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("A001")).unwrap();
+        fs::create_dir_all(root.join("backup")).unwrap();
+        let job = || CopyJob::new(root.join("A001")).destination(root.join("backup")).ascmhl(true);
+
+        assert!(job().checksum(ChecksumMethod::Xxh128).validate().is_ok());
+        for method in [ChecksumMethod::Sha256, ChecksumMethod::Blake3] {
+            assert!(matches!(job().checksum(ChecksumMethod::Md5).checksum(method).validate(), Err(Error::ChecksumNotInAscMhl(m)) if m == method));
+            assert!(job().checksum(method).ascmhl(false).validate().is_ok());
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use std::{path::Path, fs::{self, File, OpenOptions}, io::Write, collections::HashMap, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::Checksum;
+
+// Folder in the destination directory holding the state of rccopy.
+pub const STATE_FOLDER: &str = ".rccopy";
+
+// Name of the journal file inside the state folder.
+const JOURNAL_FILE: &str = "journal";
+
+// A file whose copy and verification have finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub size: u64,
+    // Modification date of the source file as seconds and nanoseconds since the unix epoch.
+    pub modified: (u64, u32),
+    pub checksums: Vec<Checksum>,
+}
+
+// Journal of the files that were copied and verified to a destination, used to resume interrupted copies.
+// Every line is one finished file: size, modification date, checksum methods, checksums and the path relative to the destination, separated by tabs.
+// Several checksum methods and checksums are separated by commas, a file without checksums has a "-" instead.
+pub struct Journal {
+    entries: HashMap<String, JournalEntry>,
+    file: Option<Mutex<File>>,
+}
+
+impl Journal {
+    // Reads the journal of a destination directory. If writable, the journal is created if needed and opened for appending.
+    pub fn open(destination: &Path, writable: bool) -> std::io::Result<Journal> {
+        let folder = destination.join(STATE_FOLDER);
+        let journal_file = folder.join(JOURNAL_FILE);
+        let mut entries: HashMap<String, JournalEntry> = HashMap::new();
+        let mut truncated = false;
+
+        if journal_file.exists() {
+            let data = fs::read(&journal_file)?;
+            for line in data.split_inclusive(|&byte| byte == b'\n') {
+                // Later entries replace earlier ones. Broken lines, e.g. from an interrupted write, are ignored.
+                // A last line without a line break may have lost the end of its path, so it is ignored as well.
+                let Some(line) = line.strip_suffix(b"\n") else {
+                    truncated = true;
+                    continue;
+                };
+                if let Some((path, entry)) = std::str::from_utf8(line).ok().and_then(parse_line) {
+                    entries.insert(path, entry);
+                }
+            }
+        }
+
+        let file = if writable {
+            fs::create_dir_all(&folder)?;
+            let mut file = OpenOptions::new().create(true).append(true).open(&journal_file)?;
+            // Terminate a truncated last line, so that it does not swallow the next entry.
+            if truncated {
+                file.write_all(b"\n")?;
+            }
+            Some(Mutex::new(file))
+        } else {
+            None
+        };
+
+        Ok(Journal { entries, file })
+    }
+
+    // Returns the entry of a file, as it was when the journal was opened.
+    pub fn get(&self, path: &str) -> Option<&JournalEntry> {
+        self.entries.get(path)
+    }
+
+    // Appends a finished file to the journal.
+    pub fn record(&self, path: &str, entry: &JournalEntry) -> std::io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        // Paths containing line breaks or tabs can not be stored, those files are simply copied again.
+        if path.contains(['\n', '\r', '\t']) {
+            return Ok(());
+        }
+
+        let (checksum_methods, checksums) = if entry.checksums.is_empty() {
+            ("-".to_string(), "-".to_string())
+        } else {
+            (
+                entry.checksums.iter().map(|checksum| checksum.method.name()).collect::<Vec<&str>>().join(","),
+                entry.checksums.iter().map(|checksum| checksum.value.as_str()).collect::<Vec<&str>>().join(","),
+            )
+        };
+
+        let line = format!(
+            "{}\t{}.{:09}\t{}\t{}\t{}\n",
+            entry.size,
+            entry.modified.0,
+            entry.modified.1,
+            checksum_methods,
+            checksums,
+            path,
+        );
+
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+// Converts a modification date to seconds and nanoseconds since the unix epoch.
+pub fn journal_time(time: SystemTime) -> Option<(u64, u32)> {
+    let duration = time.duration_since(UNIX_EPOCH).ok()?;
+    Some((duration.as_secs(), duration.subsec_nanos()))
+}
+
+// Parses a single line of the journal.
+fn parse_line(line: &str) -> Option<(String, JournalEntry)> {
+    let mut parts = line.splitn(5, '\t');
+    let size = parts.next()?.parse().ok()?;
+    let (secs, nanos) = parts.next()?.split_once('.')?;
+    let checksum_methods = parts.next()?;
+    let checksums = parts.next()?;
+    let path = parts.next()?;
+
+    let mut entry_checksums: Vec<Checksum> = Vec::new();
+    if checksum_methods != "-" {
+        let methods: Vec<&str> = checksum_methods.split(',').collect();
+        let values: Vec<&str> = checksums.split(',').collect();
+        if methods.len() != values.len() {
+            return None;
+        }
+        for (method, value) in methods.into_iter().zip(values) {
+            entry_checksums.push(Checksum { method: method.parse().ok()?, value: value.to_string() });
+        }
+    }
+
+    Some((path.to_string(), JournalEntry {
+        size,
+        modified: (secs.parse().ok()?, nanos.parse().ok()?),
+        checksums: entry_checksums,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChecksumMethod;
+
+    fn destination(name: &str) -> std::path::PathBuf {
+        let destination = std::env::temp_dir().join(format!("rccopy-journal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&destination);
+        fs::create_dir_all(&destination).unwrap();
+        destination
+    }
+
+    fn entry(size: u64, checksums: &[(ChecksumMethod, &str)]) -> JournalEntry {
+        JournalEntry {
+            size,
+            modified: (1700000000, 5),
+            checksums: checksums.iter().map(|(method, value)| Checksum { method: *method, value: value.to_string() }).collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let destination = destination("round-trip");
+        let clip = entry(1024, &[(ChecksumMethod::Xxhash64, "3f1b0c2e9d4a7b68"), (ChecksumMethod::Md5, "d41d8cd98f00b204e9800998ecf8427e")]);
+        let sidecar = entry(0, &[]);
+
+        let journal = Journal::open(&destination, true).unwrap();
+        journal.record("A001/Clips/A001C001.mov", &clip).unwrap();
+        journal.record("A001/A001.RMD", &sidecar).unwrap();
+        journal.record("A001/with\ttab", &sidecar).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&destination, false).unwrap();
+        assert_eq!(journal.get("A001/Clips/A001C001.mov"), Some(&clip));
+        assert_eq!(journal.get("A001/A001.RMD"), Some(&sidecar));
+        assert_eq!(journal.get("A001/with\ttab"), None);
+
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    #[test]
+    fn truncated_last_line() {
+        let destination = destination("truncated");
+        let clip = entry(1024, &[(ChecksumMethod::Md5, "d41d8cd98f00b204e9800998ecf8427e")]);
+
+        let journal = Journal::open(&destination, true).unwrap();
+        journal.record("A001/A001C001.mov", &clip).unwrap();
+        drop(journal);
+
+        // Simulate a write that was interrupted in the middle of the path.
+        let journal_file = destination.join(STATE_FOLDER).join(JOURNAL_FILE);
+        let mut file = OpenOptions::new().append(true).open(&journal_file).unwrap();
+        file.write_all(b"1024\t1700000000.000000005\tmd5\td41d8cd98f00b204e9800998ecf8427e\tA001/A0").unwrap();
+        drop(file);
+
+        let journal = Journal::open(&destination, true).unwrap();
+        assert_eq!(journal.get("A001/A001C001.mov"), Some(&clip));
+        assert_eq!(journal.get("A001/A0"), None);
+        journal.record("A001/A001C002.mov", &clip).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&destination, false).unwrap();
+        assert_eq!(journal.get("A001/A001C001.mov"), Some(&clip));
+        assert_eq!(journal.get("A001/A001C002.mov"), Some(&clip));
+
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    #[test]
+    fn broken_lines_are_ignored() {
+        assert!(parse_line("1024\t1700000000.5\tmd5,sha1\td41d8cd98f00b204e9800998ecf8427e\tA001/A001C001.mov").is_none());
+        assert!(parse_line("1024\t1700000000.5\tfoo\tabc\tA001/A001C001.mov").is_none());
+        assert!(parse_line("1024\t1700000000\t-\t-\tA001/A001C001.mov").is_none());
+        assert!(parse_line("size\t1700000000.5\t-\t-\tA001/A001C001.mov").is_none());
+    }
+}
@@ -0,0 +1,49 @@
+//! rccopy copies a directory to one or more destinations and verifies the copies with checksums.
+//! It can write classic MediaHashList (.mhl) files and ASC MHL histories.
+
+mod ascmhl;
+mod attributes;
+mod copy;
+mod error;
+mod filter;
+mod fsync;
+mod hash;
+mod html;
+mod job;
+mod journal;
+mod mhl;
+mod preflight;
+mod report;
+mod scan;
+mod util;
+mod verify;
+
+pub use attributes::{AttributeOptions, Ownership, copy_attributes, copy_ownership, group_id, list_attributes, user_id};
+pub use copy::{copy_file, copy_link, is_source_error, recreate_special_file};
+pub use error::Error;
+pub use html::write_html_report;
+pub use hash::{Checksum, ChecksumMethod, HashMethod, Hashers, process_checksum, process_checksum_direct};
+pub use job::{CopyJob, CopyResult, DestinationResult};
+pub use mhl::{FileMetadata, MhlEntry, write_mhl_v2, read_mhl};
+pub use preflight::{FileSystem, PreflightIssue, preflight_check};
+pub use report::{Event, FailureKind, FileRecord, ProgressMode, RecordStatus, ReportFormat, write_json_report};
+pub use scan::{SpecialFileMode, SpecialKind, SymlinkMode};
+pub use util::{format_bytes, format_bytes_per_second, format_duration};
+pub use verify::{VerifyStatus, VerifiedFile, VerifyReport, verify_mhl};
+
+// The size of the chunks to read from the input file. 8MB.
+const CHUNK_SIZE: usize = 1024 * 1024 * 8;
+
+// macOS system files that are not copied unless the built-in excludes are turned off. Files starting with "._" are excluded as well.
+const EXCLUDE_FILES: [&str; 10] = [
+    ".DS_Store",
+    ".AppleDouble",
+    ".LSOverride",
+    ".DocumentRevisions-V100",
+    ".fseventsd",
+    ".Spotlight-V100",
+    ".TemporaryItems",
+    ".Trashes",
+    ".VolumeIcon.icns",
+    ".com.apple.timemachine.donotpresent"
+];
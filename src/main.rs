@@ -1,623 +1,440 @@
-use std::{path::PathBuf, time::{SystemTime, Instant, Duration}, fs::{self, File}, io::{Read, Write}, collections::VecDeque};
-use clap::Parser;
-use chrono::{DateTime, Utc, SecondsFormat};
-use xxhash_rust::xxh64::Xxh64;
-use md5::{Md5, Digest};
-use sha1::Sha1;
-use filetime_creation::FileTime;
-use xml::writer::{EmitterConfig, XmlEvent};
-use whoami;
+use std::{path::{Path, PathBuf}, io::Write};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use rccopy::{ChecksumMethod, CopyJob, CopyResult, Error, Event, FailureKind, ProgressMode, ReportFormat, SpecialFileMode, SymlinkMode, VerifyReport, VerifyStatus, format_bytes, format_bytes_per_second, format_duration, verify_mhl};
+
+// Exit codes. The most severe problem of a run decides the code, in the order mismatch, destination, source, failed files.
+// All files were copied and verified, or all files of a verify run match.
+const EXIT_SUCCESS: i32 = 0;
+// Some files failed, e.g. a file listed in the mhl file is missing.
+const EXIT_FILES_FAILED: i32 = 1;
+// The checksums of a copy or a verified file do not match.
+const EXIT_MISMATCH: i32 = 2;
+// Invalid arguments or options, e.g. a missing input directory or an unknown checksum method.
+const EXIT_INVALID_ARGS: i32 = 3;
+// The source could not be read.
+const EXIT_SOURCE_IO: i32 = 4;
+// A destination could not be written or read back.
+const EXIT_DESTINATION_IO: i32 = 5;
 
 #[derive(Parser, Debug)]
 #[clap(name = "rccopy", about = "Copies a given input directory to a new destination directory while preserving the directory structure using checksums to verify that the files are identical after copying. Can write a mhl (MediaHashList) file containing the checksums of the copied files to the destination directory.")]
+#[clap(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Opt {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Input directory
     #[clap(short, long, required(true), help = "The source directory to copy.")]
-    input: PathBuf,
+    input: Option<PathBuf>,
 
-    /// Destination directory
-    #[clap(short, long, required(true), help = "The target directory to copy to.")]
-    destination: PathBuf,
+    /// Destination directories
+    #[clap(short, long, required(true), num_args(1..), help = "The target directories to copy to. Can be given multiple times, the source is only read once.")]
+    destination: Vec<PathBuf>,
 
-    /// Checksum method. Possible checksums: md5, sha1, xxhash64
-    #[clap(short, long, help = "The checksum method to use. Possible checksums: md5, sha1, xxhash64.")]
-    checksum: Option<String>,
+    /// Checksum methods
+    #[clap(short, long, value_enum, value_delimiter = ',', help = "The checksum methods to use, e.g. md5,xxh128. All checksums are calculated while reading the file once.")]
+    checksum: Vec<ChecksumMethod>,
 
     /// Write a mhl file to the destination directory
     #[clap(short, long, help = "Write a mhl file to the destination directory.")]
     mhl: bool,
 
-    /// Dry run. Preview the files that will be copied.
-    #[clap(long, help = "Preview the files that will be copied.")]
-    dry_run: bool,
-}
+    /// Create or update an ASC MHL history
+    #[clap(long, help = "Create or update an ASC MHL history (ascmhl folder) in the copied directory of each destination. Requires a checksum method other than sha256 and blake3.")]
+    ascmhl: bool,
 
-// Struct to hold the metadata of a file for the MediaHashList.
-struct FileMetadata {
-    file: String,
-    size: u64,
-    last_modification_date: SystemTime,
-    checksum: String,
-    checksum_method: String,
-    hash_date: SystemTime,
-}
+    /// Number of files copied at the same time
+    #[clap(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), help = "The number of files to copy and verify at the same time. With more than one job the output of every file is printed once it is done.")]
+    jobs: u16,
 
-enum HashMethod {
-    Md5(Md5),
-    Sha1(Sha1),
-    Xxh64(Xxh64),
-}
+    /// Resume an interrupted copy
+    #[clap(long, help = "Skip files that were already copied and verified according to the journal in the destination (.rccopy/journal) without reading them again. Unfinished files are copied again.")]
+    resume: bool,
 
-// The size of the chunks to read from the input file. 8MB.
-const CHUNK_SIZE: usize = 1024 * 1024 * 8;
+    /// Stop at the first error
+    #[clap(long, help = "Stop at the first file that can not be copied or verified instead of going on with the others. The mhl files and reports are still written for the files that were copied.")]
+    fail_fast: bool,
 
-fn main () {
+    /// Start despite pre-flight problems
+    #[clap(long, help = "Start even if the pre-flight check finds problems: not enough free space on a destination, files larger than its file system allows (4 GB on FAT32) or names that only differ in case on a destination that ignores case. The problems are still printed.")]
+    force: bool,
 
-    let opt: Opt = Opt::parse();
+    /// Sync copied files to the disk
+    #[clap(long, num_args(0..=1), default_missing_value = "true", help = "Sync every copied file and its directory to the disk before it is verified. On by default if a checksum method is given, use --fsync false to turn it off.")]
+    fsync: Option<bool>,
 
-    let start_date = format_system_time_to_rfc3339(SystemTime::now());
-    let start_date_for_file_name: String = start_date.replace(":", "").replace("T", "_").replace("Z", "");
+    /// Drop copied files from the page cache
+    #[clap(long, help = "Drop every copied file from the page cache before it is verified, so the verification reads the data from the disk. Only supported on Linux. Implies --fsync.")]
+    drop_cache: bool,
 
-    // Check if the input and destination directorys exist. Print as Error.
-    if !opt.input.exists() {
-        eprintln!("Error: Input directory does not exist.");
-        std::process::exit(1);
-    }
-    if !opt.destination.exists() {
-        eprintln!("Error: Destination directory does not exist.");
-        std::process::exit(1);
-    }
+    /// Verify with direct I/O
+    #[clap(long, help = "Read the destination files with direct I/O (O_DIRECT) when verifying them, bypassing the page cache. Falls back to normal reads with a warning where it is not supported, e.g. on tmpfs. Only supported on Linux.")]
+    verify_direct: bool,
 
-    // Check if the input and destination directorys are directories. Print as Error.
-    if !opt.input.is_dir() {
-        eprintln!("Error: Input is not a directory.");
-        std::process::exit(1);
-    }
-    if !opt.destination.is_dir() {
-        eprintln!("Error: Destination is not a directory.");
-        std::process::exit(1);
-    }
+    /// Trust existing files with identical size and modification date
+    #[clap(long, help = "Skip files that already exist in the destination with identical file size and modification date without reading them. Checksums for the mhl files are taken from earlier mhl files in the destination.")]
+    trust_size_and_mtime: bool,
 
-    // Check if the input and destination directorys are the same. Print as Error.
-    if opt.input == opt.destination {
-        eprintln!("Error: Input and destination directorys are the same.");
-        std::process::exit(1);
-    }
+    /// Exclude patterns
+    #[clap(short, long, value_name = "PATTERN", help = "Glob pattern of files and directories not to copy, e.g. '*.tmp' or 'CACHE/'. Can be given multiple times. More patterns can be listed in a .rccopyignore file in the source directory.")]
+    exclude: Vec<String>,
 
-    // Search the input directory recursively for files.
-    let files: Vec<PathBuf> = get_files_in_directory(&opt.input);
-
-    // Search the destination directory recursively for empty directories.
-    let empty_dirs: Vec<PathBuf> = get_empty_dirs(&opt.input);
-
-    // Initialze some stuff
-    let mut failed_files: Vec<PathBuf> = Vec::new();
-    let mut had_errors = false;
-    let mut copied_anything = false;
-    let total_files = files.len();
-    let mut mhl_data: Vec<FileMetadata> = Vec::new();
-
-    // Copy the files.
-    for file in &files {
-
-        // Destination file
-        let destination_file = opt.destination.join(file.strip_prefix(&opt.input.parent().unwrap()).unwrap());
-
-        // Check if the file already exists in the destination directory. Verify that the file sizes match and the checksums match.
-        if destination_file.exists() && destination_file.metadata().unwrap().len() == file.metadata().unwrap().len() {
-            println!("-------------------------");
-            println!("{} / {}: File {} already exists and has identical file size. Verifying checksums...", files.iter().position(|x| x == file).unwrap() + 1, total_files, destination_file.display());
-
-            if !opt.dry_run {
-                let src_checksum = process_checksum(&file.to_str().unwrap(), &opt.checksum);
-                let dest_checksum = process_checksum(&destination_file.to_str().unwrap(), &opt.checksum);
-    
-                if src_checksum.is_err() {
-                    eprintln!("Error: Could not verify checksum.");
-                    failed_files.push(file.clone());
-                    had_errors = true;
-                    continue;
-                } else if dest_checksum.is_err() {
-                    eprintln!("Error: Could not verify checksum.");
-                    failed_files.push(file.clone());
-                    had_errors = true;
-                    continue;
-                } else if src_checksum.as_ref().unwrap() == dest_checksum.as_ref().unwrap() {
-                    println!("Checksums match: {} ({})", src_checksum.as_ref().unwrap(), opt.checksum.as_ref().unwrap());
-                    let checksum_method = if opt.checksum.as_ref().unwrap() == "xxhash64" {
-                        "xxhash64be".to_string()
-                    } else {
-                        opt.checksum.as_ref().unwrap().to_string()
-                    };
-                    mhl_data.push(FileMetadata {
-                        file: destination_file.strip_prefix(&opt.destination).unwrap().to_str().unwrap().to_string(),
-                        size: file.metadata().unwrap().len(),
-                        last_modification_date: file.metadata().unwrap().modified().unwrap(),
-                        checksum: src_checksum.unwrap(),
-                        checksum_method,
-                        hash_date: SystemTime::now(),
-                    });
-                    continue;
-                } else {
-                    println!("Error: Checksums do not match. File was not copied successfully.");
-                    failed_files.push(file.clone());
-                    had_errors = true;
-                    continue;
-                }
-            }
-        }
+    /// Include patterns
+    #[clap(long, value_name = "PATTERN", help = "Glob pattern of files to copy even if they are excluded. Can be given multiple times.")]
+    include: Vec<String>,
 
-        println!("-------------------------");
-        println!("{} / {}: {} --> {}", files.iter().position(|x| x == file).unwrap() + 1, total_files, file.display(), destination_file.display());
+    /// Copy macOS system files
+    #[clap(long, help = "Do not skip the macOS system files (.DS_Store, .Spotlight-V100, ._* and so on).")]
+    no_default_excludes: bool,
 
-        if opt.dry_run {
-            continue;
-        }
+    /// Symbolic link handling
+    #[clap(long, value_enum, default_value_t = SymlinkMode::Follow, help = "How symbolic links are handled. follow copies the file or directory they point to and skips links leading back into a parent directory, copy-as-link recreates the link in the destination and skip leaves them out. Links copied as links are not listed in the mhl files.")]
+    symlinks: SymlinkMode,
 
-        let src_checksum = copy_file(file, &destination_file, &opt.checksum);
-
-        if src_checksum.is_err() {
-            eprintln!("Error: Could not copy file.");
-            failed_files.push(file.clone());
-            had_errors = true;
-            continue;  
-        } else if src_checksum.as_ref().unwrap() == "None" {
-            copied_anything = true;
-            println!();
-            continue;
-        } else {
-            copied_anything = true;
-
-            let dest_checksum = process_checksum(&destination_file.to_str().unwrap(), &opt.checksum);
-
-            if dest_checksum.is_err() {
-                eprintln!("Error: Could not verify checksum.");
-                failed_files.push(file.clone());
-                had_errors = true;
-                continue;
-            } else if src_checksum.as_ref().unwrap() == dest_checksum.as_ref().unwrap() {
-                println!("Checksums match: {} ({})", src_checksum.as_ref().unwrap(), opt.checksum.as_ref().unwrap());
-                let checksum_method = if opt.checksum.as_ref().unwrap() == "xxhash64" {
-                    "xxhash64be".to_string()
-                } else {
-                    opt.checksum.as_ref().unwrap().to_string()
-                };
-                mhl_data.push(FileMetadata {
-                    file: destination_file.strip_prefix(&opt.destination).unwrap().to_str().unwrap().to_string(),
-                    size: file.metadata().unwrap().len(),
-                    last_modification_date: file.metadata().unwrap().modified().unwrap(),
-                    checksum: src_checksum.unwrap(),
-                    checksum_method,
-                    hash_date: SystemTime::now(),
-                });
-                continue;
-            } else {
-                println!("Error: Checksums do not match. File was not copied successfully. ({})", opt.checksum.as_ref().unwrap());
-                failed_files.push(file.clone());
-                had_errors = true;
-                continue;
-            }
-        }
-    }
+    /// Special file handling
+    #[clap(long, value_enum, default_value_t = SpecialFileMode::Skip, help = "How named pipes, sockets and device nodes are handled. skip leaves them out, recreate creates new named pipes and device nodes in the destination. Sockets are always skipped. Skipped special files are listed in the summary.")]
+    special_files: SpecialFileMode,
 
-    // Create the empty directories in the destination directory.
-    for dir in empty_dirs {
-        let destination_dir = opt.destination.join(dir.strip_prefix(&opt.input.parent().unwrap()).unwrap());
-        if !destination_dir.exists() {
-            if !opt.dry_run {
-                fs::create_dir_all(destination_dir).unwrap();
-            }
-        }
-    }
+    /// Copy extended attributes
+    #[clap(long, help = "Copy the extended attributes of the files, e.g. Finder tags and com.apple.metadata on macOS or user.* attributes on Linux. Attributes that can not be copied are listed in the summary.")]
+    xattrs: bool,
 
-    if opt.mhl && copied_anything && !opt.dry_run {
-        println!("-------------------------");
-        println!("Writing mhl file...");
+    /// Copy ACLs
+    #[clap(long, help = "Copy the ACLs of the files. ACLs that can not be copied are listed in the summary.")]
+    acls: bool,
 
-        // MHL file name is the basedir of the source directory + the current date and time + .mhl
-        let mhl_file = opt.destination.join(format!("{}_{}.mhl", opt.input.file_name().unwrap().to_str().unwrap(), start_date_for_file_name));
+    /// Preserve the owner
+    #[clap(long, help = "Give the copies the owner of the source files and directories. Usually requires root.")]
+    owner: bool,
 
-        let mhl_result = write_mhl_v2(&mhl_file, mhl_data, start_date);
+    /// Preserve the group
+    #[clap(long, help = "Give the copies the group of the source files and directories.")]
+    group: bool,
 
-        if mhl_result.is_err() {
-            eprintln!("Error: Could not write mhl file.");
-            std::process::exit(1);
-        }
-    }
+    /// Map the owner and group
+    #[clap(long, value_name = "USER:GROUP", help = "Give all copies this owner and group, by name or id, instead of the ones of the source. Either part can be left out, e.g. 'editor' or ':staff'.")]
+    chown: Option<String>,
 
-    println!("-------------------------");
+    /// Progress output
+    #[clap(long, value_enum, default_value_t = ProgressMode::Text, help = "How the progress is shown. text prints human readable lines and transfer speeds, json prints newline-delimited JSON events (file_started, bytes_progress, file_verified, file_failed, job_finished) on stdout and the human readable lines on stderr.")]
+    progress: ProgressMode,
 
-    if opt.dry_run {
-        println!("Finished dry run.");
-    } else if had_errors {
-        println!("Finished with errors.");
-        println!("Failed files:");
-        for file in failed_files {
-            println!("{}", file.display());
-        }
-    } else if copied_anything{
-        println!("Finished successfully. 🎉");
-    } else {
-        println!("Nothing to copy.");
-    }
-}
+    /// Report file
+    #[clap(long, num_args(2), action = ArgAction::Set, value_names = ["FORMAT", "PATH"], help = "Write a report of every file with its source, destination, size, checksums, duration, status and error once the job is done, e.g. '--report json report.json'. Possible formats: json.")]
+    report: Vec<String>,
 
-// Searches the given directory recursively for files and returns a vector of the files.
-fn get_files_in_directory(dir: &PathBuf) -> Vec<PathBuf> {
-    let mut files: Vec<PathBuf> = Vec::new();
-    let exclude_files = [
-        ".DS_Store",
-        ".AppleDouble",
-        ".LSOverride",
-        ".DocumentRevisions-V100",
-        ".fseventsd",
-        ".Spotlight-V100",
-        ".TemporaryItems",
-        ".Trashes",
-        ".VolumeIcon.icns",
-        ".com.apple.timemachine.donotpresent"
-    ];
-
-    for entry in fs::read_dir(dir).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-
-        if path.is_dir() {
-            files.append(&mut get_files_in_directory(&path));
-        } else if let Some(file_name) = path.file_name() {
-            if let Some(file_name_str) = file_name.to_str() {
-                if !exclude_files.contains(&file_name_str) && !file_name_str.starts_with("._") {
-                    files.push(path);
-                }
-            }
-        }
-    }
+    /// HTML report
+    #[clap(long, help = "Write a printable HTML report with the job info, volumes, totals, every file with its checksums and the failures to each destination directory, next to the mhl file.")]
+    html_report: bool,
+
+    /// Dry run. Preview the files that will be copied.
+    #[clap(long, help = "Preview the files that will be copied.")]
+    dry_run: bool,
+}
 
-    files
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Verify a directory against an existing mhl file
+    #[clap(about = "Verifies the files listed in an existing mhl file by recalculating their checksums. Exits with an error if any file is missing, changed or does not match.")]
+    Verify {
+        /// The mhl file to verify against
+        #[clap(short, long, required(true), help = "The mhl file to verify against.")]
+        mhl: PathBuf,
+
+        /// Root directory of the paths in the mhl file
+        #[clap(short, long, help = "The directory the paths in the mhl file are relative to. Defaults to the directory containing the mhl file.")]
+        root: Option<PathBuf>,
+    },
 }
 
-// Searches the given directory recursively for empty directories and returns a vector of the empty directories.
-fn get_empty_dirs (dir: &PathBuf) -> Vec<PathBuf> {
-    let mut empty_dirs: Vec<PathBuf> = Vec::new();
+fn main () {
 
-    for entry in fs::read_dir(dir).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
+    // Usage errors exit with EXIT_INVALID_ARGS instead of the 2 clap uses, which is taken by mismatches. Help and version still exit with 0.
+    let opt: Opt = match Opt::try_parse() {
+        Ok(opt) => opt,
+        Err(e) => {
+            let _ = e.print();
+            std::process::exit(if e.use_stderr() { EXIT_INVALID_ARGS } else { EXIT_SUCCESS });
+        }
+    };
 
-        if path.is_dir() {
-            let mut is_empty = true;
-            for _ in fs::read_dir(&path).unwrap() {
-                is_empty = false;
-                break;
+    if let Some(Command::Verify { mhl, root }) = opt.command {
+        let root = root.unwrap_or_else(|| mhl.parent().map(|parent| parent.to_path_buf()).unwrap_or_default());
+        match verify_mhl(&mhl, &root, |event| print_event(event, ProgressMode::Text)) {
+            Ok(report) => {
+                print_verify_summary(&report);
+                std::process::exit(verify_exit_code(&report));
             }
-            if is_empty {
-                empty_dirs.push(path.clone());
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                // Without a readable mhl file or directory nothing could be verified.
+                let code = match e {
+                    Error::Io { .. } | Error::Mhl { .. } => EXIT_SOURCE_IO,
+                    _ => EXIT_INVALID_ARGS,
+                };
+                std::process::exit(code);
             }
-            let sub_dirs = get_empty_dirs(&path);
-            empty_dirs.extend(sub_dirs);
         }
     }
 
-    empty_dirs
-}
-
-// Copy a file from the input directory to the destination directory.
-fn copy_file (input_path: &PathBuf, destination_path: &PathBuf, checksum_method: &Option<String>) -> Result<String, std::io::Error> {
+    // Input is required without a subcommand.
+    let Some(input) = opt.input else {
+        eprintln!("Error: No input directory given.");
+        std::process::exit(EXIT_INVALID_ARGS);
+    };
 
-    // Create the destination directory if it doesnt exist.
-    if !destination_path.parent().unwrap().exists() {
-        fs::create_dir_all(destination_path.parent().unwrap()).unwrap();
+    let mut job = CopyJob::new(input.clone())
+        .mhl(opt.mhl)
+        .ascmhl(opt.ascmhl)
+        .dry_run(opt.dry_run)
+        .jobs(opt.jobs as usize)
+        .resume(opt.resume)
+        .fail_fast(opt.fail_fast)
+        .force(opt.force)
+        .drop_cache(opt.drop_cache)
+        .verify_direct(opt.verify_direct)
+        .trust_size_and_mtime(opt.trust_size_and_mtime)
+        .default_excludes(!opt.no_default_excludes)
+        .symlinks(opt.symlinks)
+        .special_files(opt.special_files)
+        .xattrs(opt.xattrs)
+        .acls(opt.acls)
+        .owner(opt.owner)
+        .group(opt.group)
+        .progress(opt.progress)
+        .on_event(move |event| print_event(event, opt.progress))
+        .html_report(opt.html_report);
+    for destination in opt.destination {
+        job = job.destination(destination);
     }
-
-    // Open the input file.
-    let mut input_file = fs::File::open(input_path).unwrap();
-
-    // Create the destination file.
-    let mut destination_file = fs::File::create(destination_path).unwrap();
-
-    // Initialize some variables.
-    let mut buffer = vec![0; CHUNK_SIZE];
-    let mut total_bytes_read = 0;
-    let mut last_print_time = Instant::now();
-
-    // Check if a checksum method was given.
-    if checksum_method.is_some() {
-
-        let mut hasher: HashMethod = match checksum_method.as_ref().unwrap().as_str() {
-            "md5" => HashMethod::Md5(Md5::new()),
-            "sha1" => HashMethod::Sha1(Sha1::new()),
-            "xxhash64" => HashMethod::Xxh64(Xxh64::new(0)),
-            _ => {
-                eprintln!("Error: Invalid checksum method.");
-                std::process::exit(1);
-            }
-        };
-
-        // Print a placeholder for the transfer speed.
-        print!("\rTransfer speed: {:30}\r", "---.-- MB/s");
-        std::io::stdout().flush().unwrap();
-
-        // Copy the file. With checksum.
-        let mut transfer_readings = VecDeque::new();
-        let window_size = 10;  // Use last 10 readings to calculate the speed
-
-        loop {
-            let bytes_read = input_file.read(&mut buffer).unwrap();
-
-            if bytes_read == 0 {
-                break;
-            }
-            destination_file.write_all(&buffer[..bytes_read]).unwrap();
-
-            // Update hash
-            match &mut hasher {
-                HashMethod::Md5(h) => h.update(&buffer[..bytes_read]),
-                HashMethod::Sha1(h) => h.update(&buffer[..bytes_read]),
-                HashMethod::Xxh64(h) => h.update(&buffer[..bytes_read]),
-            };
-
-            total_bytes_read += bytes_read;
-            
-            // Print transfer speed every 100 ms. Use the format bytes function to format the bytes.
-            let elapsed = last_print_time.elapsed();
-
-            if elapsed > Duration::from_millis(100) {
-                std::io::stdout().flush().unwrap();
-                let bytes_per_second = total_bytes_read as f64 / elapsed.as_secs_f64();
-
-                // Use a moving window to smooth the transfer speed
-                if transfer_readings.len() >= window_size {
-                    transfer_readings.pop_front();
-                }
-                transfer_readings.push_back(bytes_per_second);
-
-                let avg_bytes_per_second: f64 = transfer_readings.iter().sum::<f64>() / transfer_readings.len() as f64;
-
-                print!("\rTransfer speed: {:30}\r", format_bytes_per_second(avg_bytes_per_second as u64));
-                last_print_time = Instant::now();
-                total_bytes_read = 0;  // reset total_bytes_read here
-            }
+    for pattern in opt.exclude {
+        job = job.exclude(pattern);
+    }
+    for pattern in opt.include {
+        job = job.include(pattern);
+    }
+    for checksum in opt.checksum {
+        job = job.checksum(checksum);
+    }
+    if let Some(chown) = opt.chown {
+        let (user, group) = chown.split_once(':').unwrap_or((&chown, ""));
+        if !user.is_empty() {
+            job = job.map_user(user);
         }
-
-
-        // Compute and return the checksum
-        let hash_string = match hasher {
-            HashMethod::Md5(h) => format!("{:032x}", h.finalize()),
-            HashMethod::Sha1(h) => format!("{:040x}", h.finalize()),
-            HashMethod::Xxh64(h) => format!("{:016x}", h.digest()),
+        if !group.is_empty() {
+            job = job.map_group(group);
+        }
+    }
+    if let [format, path] = &opt.report[..] {
+        let Ok(format) = ReportFormat::from_str(format, true) else {
+            eprintln!("Error: Invalid report format: {}", format);
+            std::process::exit(EXIT_INVALID_ARGS);
         };
+        job = job.report(format, path);
+    }
+    if let Some(fsync) = opt.fsync {
+        job = job.fsync(fsync);
+    }
 
-        // Copy the metadata
-        let metadata = std::fs::metadata(input_path)?;
-        let permissions = metadata.permissions();
-        std::fs::set_permissions(destination_path, permissions)?;
-
-        let accessed = FileTime::from_last_access_time(&metadata);
-        let modified = FileTime::from_last_modification_time(&metadata);
-        let created = FileTime::from_creation_time(&metadata);
-
-        filetime_creation::set_file_times(destination_path, accessed, modified, created.unwrap())?;
-
-        Ok(hash_string)
-
-    } else {
-        // Print a placeholder for the transfer speed.
-        print!("\rTransfer speed: {:30}\r", "---.-- MB/s");
-        std::io::stdout().flush().unwrap();
-
-        // Copy the file.
-        let mut transfer_readings = VecDeque::new();
-        let window_size = 10;  // Use last 10 readings to calculate the speed
-
-        loop {
-            let bytes_read = input_file.read(&mut buffer).unwrap();
-            if bytes_read == 0 {
-                break;
+    match job.run() {
+        Ok(result) => {
+            // With JSON events on stdout the summary goes to stderr.
+            match opt.progress {
+                ProgressMode::Text => print_copy_summary(&mut std::io::stdout(), &result, opt.dry_run),
+                ProgressMode::Json => print_copy_summary(&mut std::io::stderr(), &result, opt.dry_run),
             }
-            destination_file.write_all(&buffer[..bytes_read]).unwrap();
-            total_bytes_read += bytes_read;
-            
-            // Print transfer speed every 100 ms. Use the format bytes function to format the bytes.
-            let elapsed = last_print_time.elapsed();
-
-            if elapsed > Duration::from_millis(100) {
-                std::io::stdout().flush().unwrap();
-                let bytes_per_second = total_bytes_read as f64 / elapsed.as_secs_f64();
-
-                // Use a moving window to smooth the transfer speed
-                if transfer_readings.len() >= window_size {
-                    transfer_readings.pop_front();
-                }
-                transfer_readings.push_back(bytes_per_second);
-
-                let avg_bytes_per_second: f64 = transfer_readings.iter().sum::<f64>() / transfer_readings.len() as f64;
-
-                print!("\rTransfer speed: {:30}\r", format_bytes_per_second(avg_bytes_per_second as u64));
-                last_print_time = Instant::now();
-                total_bytes_read = 0;  // reset total_bytes_read here
+            std::process::exit(copy_exit_code(&result));
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            if matches!(e, Error::Preflight(_)) {
+                eprintln!("Use --force to start anyway.");
             }
+            std::process::exit(error_exit_code(&e, &input));
         }
+    }
+}
 
-
-        // Copy the metadata
-        let metadata = std::fs::metadata(input_path)?;
-        let permissions = metadata.permissions();
-        std::fs::set_permissions(destination_path, permissions)?;
-
-        let accessed = FileTime::from_last_access_time(&metadata);
-        let modified = FileTime::from_last_modification_time(&metadata);
-        let created = FileTime::from_creation_time(&metadata);
-
-        filetime_creation::set_file_times(destination_path, accessed, modified, created.unwrap())?;
-
-        Ok("None".to_string())
+// Prints an event of a copy job or verify run. With JSON events on stdout the human readable lines go to stderr.
+fn print_event(event: Event, progress: ProgressMode) {
+    match event {
+        Event::Line(line) if progress == ProgressMode::Text => println!("{}", line),
+        Event::Line(line) | Event::ErrorLine(line) => eprintln!("{}", line),
+        // The speed is printed on a single line that is overwritten with every update.
+        Event::Speed { label, speed } => {
+            print!("\r{} {:30}\r", label, speed);
+            let _ = std::io::stdout().flush();
+        }
+        Event::SpeedDone { keep: true } => println!(),
+        Event::SpeedDone { keep: false } => {
+            print!("\r\x1B[K");
+            let _ = std::io::stdout().flush();
+        }
+        Event::Json(value) => {
+            // The lock keeps events of parallel jobs on their own lines.
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{}", value);
+            let _ = stdout.flush();
+        }
     }
 }
 
-// Process the checksum of a file.
-fn process_checksum(input_file: &str, checksum_method: &Option<String>) -> Result<String, std::io::Error> {
+// Exit code of a finished copy job.
+fn copy_exit_code(result: &CopyResult) -> i32 {
+    if result.has_failure(FailureKind::Mismatch) {
+        EXIT_MISMATCH
+    } else if result.has_failure(FailureKind::Destination) || result.report_error.is_some() {
+        EXIT_DESTINATION_IO
+    } else if result.has_failure(FailureKind::Source) {
+        EXIT_SOURCE_IO
+    } else if result.had_errors() {
+        EXIT_FILES_FAILED
+    } else {
+        EXIT_SUCCESS
+    }
+}
 
-    print!("\rVerifying checksum... ({}) Speed: {:30}\r", checksum_method.as_ref().unwrap().as_str(), "---.-- MB/s");
+// Exit code of a copy job that could not run or was stopped. I/O errors are told apart by their path.
+fn error_exit_code(error: &Error, input: &Path) -> i32 {
+    match error {
+        Error::Io { path, .. } if path.starts_with(input) => EXIT_SOURCE_IO,
+        Error::Io { .. } | Error::Mhl { .. } | Error::Preflight(_) => EXIT_DESTINATION_IO,
+        _ => EXIT_INVALID_ARGS,
+    }
+}
 
-    let mut buffer = vec![0; CHUNK_SIZE];
-    let mut total_bytes_read = 0;
-    let mut last_print_time = Instant::now();
+// Exit code of a finished verify run.
+fn verify_exit_code(report: &VerifyReport) -> i32 {
+    let failed: Vec<&VerifyStatus> = report.failed_files().map(|file| &file.status).collect();
+    if failed.iter().any(|status| matches!(status, VerifyStatus::Mismatch | VerifyStatus::SizeChanged(_))) {
+        EXIT_MISMATCH
+    } else if failed.iter().any(|status| matches!(status, VerifyStatus::Error(_))) {
+        EXIT_SOURCE_IO
+    } else if !failed.is_empty() {
+        EXIT_FILES_FAILED
+    } else {
+        EXIT_SUCCESS
+    }
+}
 
-    // Open the input file.
-    let mut input_file = fs::File::open(input_file).unwrap();
+// Prints the final summary of a copy job. Write errors are ignored, like println! would panic on them instead.
+fn print_copy_summary(out: &mut dyn Write, result: &CopyResult, dry_run: bool) {
+    let _ = writeln!(out, "-------------------------");
 
-    let mut hasher: HashMethod = match checksum_method.as_ref().unwrap().as_str() {
-        "md5" => HashMethod::Md5(Md5::new()),
-        "sha1" => HashMethod::Sha1(Sha1::new()),
-        "xxhash64" => HashMethod::Xxh64(Xxh64::new(0)),
-        _ => {
-            eprintln!("Error: Invalid checksum method.");
-            std::process::exit(1);
+    if !result.skipped_special_files.is_empty() {
+        let _ = writeln!(out, "Skipped special files:");
+        for (file, kind) in &result.skipped_special_files {
+            let _ = writeln!(out, "{} ({})", file.display(), kind);
         }
-    };
-
-    // Calculate the checksum of the file.
-    let mut readings = VecDeque::new();
-    let window_size = 10;  // Use last 10 readings to calculate the speed
+        let _ = writeln!(out, "-------------------------");
+    }
 
-    loop {
-        let bytes_read = input_file.read(&mut buffer).unwrap();
-    
-        if bytes_read == 0 {
-            break;
+    if !result.skipped_links.is_empty() {
+        let _ = writeln!(out, "Skipped symbolic links:");
+        for link in &result.skipped_links {
+            let _ = writeln!(out, "{}", link.display());
         }
-    
-        total_bytes_read += bytes_read;
-    
-        // Update hash
-        match &mut hasher {
-            HashMethod::Md5(h) => h.update(&buffer[..bytes_read]),
-            HashMethod::Sha1(h) => h.update(&buffer[..bytes_read]),
-            HashMethod::Xxh64(h) => h.update(&buffer[..bytes_read]),
-        };
-    
-        // Print transfer speed every 100 ms. Use the format bytes function to format the bytes.
-        let elapsed = last_print_time.elapsed();
-    
-        if elapsed > Duration::from_millis(100) {
-            std::io::stdout().flush().unwrap();
-            let bytes_per_second = total_bytes_read as f64 / elapsed.as_secs_f64();
-
-            // Use a moving window to smooth the transfer speed
-            if readings.len() >= window_size {
-                readings.pop_front();
-            }
-            readings.push_back(bytes_per_second);
-
-            let avg_bytes_per_second: f64 = readings.iter().sum::<f64>() / readings.len() as f64;
+        let _ = writeln!(out, "-------------------------");
+    }
 
-            print!("\rVerifying checksum... ({}) Speed: {:30}\r", checksum_method.as_ref().unwrap().as_str(), format_bytes_per_second(avg_bytes_per_second as u64));
-            last_print_time = Instant::now();
-            total_bytes_read = 0;  // reset total_bytes_read here
+    if !result.excluded_dirs.is_empty() {
+        let _ = writeln!(out, "Skipped directories:");
+        for dir in &result.excluded_dirs {
+            let _ = writeln!(out, "{}", dir.display());
         }
+        let _ = writeln!(out, "-------------------------");
     }
 
-    // Compute and return the checksum
-    let hash_string = match hasher {
-        HashMethod::Md5(h) => format!("{:032x}", h.finalize()),
-        HashMethod::Sha1(h) => format!("{:040x}", h.finalize()),
-        HashMethod::Xxh64(h) => format!("{:016x}", h.digest()),
-    };
-
-    print!("\r\x1B[K");
-    std::io::stdout().flush().unwrap();    
-
-    Ok(hash_string)
+    for destination in result.destinations.iter().filter(|destination| !destination.metadata_failures.is_empty()) {
+        let _ = writeln!(out, "{}: Could not copy all metadata of:", destination.path.display());
+        for (file, failure) in &destination.metadata_failures {
+            let _ = writeln!(out, "{} ({})", file.display(), failure);
+        }
+        let _ = writeln!(out, "-------------------------");
+    }
 
-}
+    if let Some(error) = &result.report_error {
+        let _ = writeln!(out, "Error: {}", error);
+        let _ = writeln!(out, "-------------------------");
+    }
 
-// Formats a SystemTime to a RFC3339 string.
-fn format_system_time_to_rfc3339(st: SystemTime) -> String {
-    let datetime: DateTime<Utc> = st.into();
-    datetime.to_rfc3339_opts(SecondsFormat::Secs, true)
-}
+    if !dry_run {
+        print_totals(out, result);
+    }
 
-// Formats Bytes/s to a human readable string.
-fn format_bytes_per_second(bytes: u64) -> String {
-    let kb: u64 = 1024;
-    let mb: u64 = kb * 1024;
-    let gb: u64 = mb * 1024;
-    let tb: u64 = gb * 1024;
-
-    if bytes < kb {
-        format!("{} B/s", bytes)
-    } else if bytes < mb {
-        format!("{:.2} KB/s", bytes as f64 / kb as f64)
-    } else if bytes < gb {
-        format!("{:.2} MB/s", bytes as f64 / mb as f64)
-    } else if bytes < tb {
-        format!("{:.2} GB/s", bytes as f64 / gb as f64)
+    if dry_run {
+        let _ = writeln!(out, "Finished dry run.");
+    } else if result.had_errors() {
+        if result.stopped {
+            let _ = writeln!(out, "Stopped at the first error, the remaining files were not copied.");
+        }
+        let _ = writeln!(out, "Finished with errors.");
+        for destination in &result.destinations {
+            if destination.failed.is_empty() {
+                let _ = writeln!(out, "{}: OK", destination.path.display());
+                continue;
+            }
+            let _ = writeln!(out, "{}: Failed files:", destination.path.display());
+            for file in &destination.failed {
+                let _ = writeln!(out, "{}", file.display());
+            }
+        }
+    } else if result.copied_anything() {
+        let _ = writeln!(out, "Finished successfully. 🎉");
     } else {
-        format!("{:.2} TB/s", bytes as f64 / tb as f64)
+        let _ = writeln!(out, "Nothing to copy.");
     }
 }
 
-// Writes a mhl file to the destination directory.
-fn write_mhl_v2(destination_path: &PathBuf, metadata: Vec<FileMetadata>, start_date: String) -> std::io::Result<()> {
-    let file = File::create(&destination_path)?;
-    let mut writer = EmitterConfig::new()
-        .perform_indent(true)
-        .create_writer(file);
-
-    writer.write(XmlEvent::start_element("hashlist").attr("version", "1.1")).unwrap();
-
-    // Reading the system information
-    let name = whoami::realname();
-    let username = whoami::username();
-    let hostname = whoami::hostname();
-    let tool = format!("{} ver. {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    let finish_date = format_system_time_to_rfc3339(SystemTime::now());
-
-    writer.write(XmlEvent::start_element("creatorinfo")).unwrap();
-    writer.write(XmlEvent::start_element("name")).unwrap();
-    writer.write(XmlEvent::characters(name.as_str())).unwrap();
-    writer.write(XmlEvent::end_element()).unwrap();
-    writer.write(XmlEvent::start_element("username")).unwrap();
-    writer.write(XmlEvent::characters(username.as_str())).unwrap();
-    writer.write(XmlEvent::end_element()).unwrap();
-    writer.write(XmlEvent::start_element("hostname")).unwrap();
-    writer.write(XmlEvent::characters(hostname.as_str())).unwrap();
-    writer.write(XmlEvent::end_element()).unwrap();
-    writer.write(XmlEvent::start_element("tool")).unwrap();
-    writer.write(XmlEvent::characters(tool.as_str())).unwrap();
-    writer.write(XmlEvent::end_element()).unwrap();
-    writer.write(XmlEvent::start_element("startdate")).unwrap();
-    writer.write(XmlEvent::characters(start_date.as_str())).unwrap();
-    writer.write(XmlEvent::end_element()).unwrap();
-    writer.write(XmlEvent::start_element("finishdate")).unwrap();
-    writer.write(XmlEvent::characters(finish_date.as_str())).unwrap();
-    writer.write(XmlEvent::end_element()).unwrap();
-    writer.write(XmlEvent::end_element()).unwrap();
-
-    for item in metadata {
-        writer.write(XmlEvent::start_element("hash")).unwrap();
-        writer.write(XmlEvent::start_element("file")).unwrap();
-        let file_path = PathBuf::from(&item.file);
-        let relative_path = file_path.strip_prefix(&destination_path).unwrap_or(&file_path);
-        writer.write(XmlEvent::characters(relative_path.to_string_lossy().as_ref())).unwrap();
-        writer.write(XmlEvent::end_element()).unwrap();
-        writer.write(XmlEvent::start_element("size")).unwrap();
-        writer.write(XmlEvent::characters(item.size.to_string().as_str())).unwrap();
-        writer.write(XmlEvent::end_element()).unwrap();
-        writer.write(XmlEvent::start_element("lastmodificationdate")).unwrap();
-        writer.write(XmlEvent::characters(format_system_time_to_rfc3339(item.last_modification_date).as_str())).unwrap();
-        writer.write(XmlEvent::end_element()).unwrap();
-        writer.write(XmlEvent::start_element(item.checksum_method.as_str())).unwrap();
-        writer.write(XmlEvent::characters(item.checksum.as_str())).unwrap();
-        writer.write(XmlEvent::end_element()).unwrap();
-        writer.write(XmlEvent::start_element("hashdate")).unwrap();
-        writer.write(XmlEvent::characters(format_system_time_to_rfc3339(item.hash_date).as_str())).unwrap();
-        writer.write(XmlEvent::end_element()).unwrap();
-        writer.write(XmlEvent::end_element()).unwrap();
+// Prints the number of copied, skipped, failed and excluded files per destination, the bytes copied, the elapsed time and the average throughput.
+fn print_totals(out: &mut dyn Write, result: &CopyResult) {
+    let _ = writeln!(out, "Summary:");
+    for destination in &result.destinations {
+        let _ = writeln!(out, "{}: {} copied ({}), {} skipped (identical), {} failed",
+            destination.path.display(),
+            destination.copied.len(),
+            format_bytes(destination.bytes_copied()),
+            destination.skipped.len(),
+            destination.failed.len());
     }
+    let _ = writeln!(out, "Excluded: {} files, {} directories", result.excluded.len(), result.excluded_dirs.len());
+
+    // The source is read once, so the throughput counts the bytes written to all destinations together.
+    let bytes_copied: u64 = result.destinations.iter().map(|destination| destination.bytes_copied()).sum();
+    let seconds = result.duration.as_secs_f64();
+    let _ = writeln!(out, "Total: {} copied in {}", format_bytes(bytes_copied), format_duration(result.duration));
+    if seconds > 0.0 {
+        let _ = writeln!(out, "Average throughput: {}", format_bytes_per_second((bytes_copied as f64 / seconds) as u64));
+    }
+    let _ = writeln!(out, "-------------------------");
+}
 
-    writer.write(XmlEvent::end_element()).unwrap();
+// Prints the final summary of a verify run.
+fn print_verify_summary(report: &VerifyReport) {
+    println!("-------------------------");
 
+    if !report.unlisted_files.is_empty() {
+        println!("Files not in the mhl file:");
+        for file in &report.unlisted_files {
+            println!("{}", file.display());
+        }
+        println!("-------------------------");
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    if report.is_ok() {
+        println!("Verified {} files successfully. 🎉", report.files.len());
+    } else {
+        println!("Finished with errors.");
+        println!("Failed files:");
+        for file in report.failed_files() {
+            let reason = match &file.status {
+                VerifyStatus::Mismatch => "checksum mismatch".to_string(),
+                VerifyStatus::Missing => "missing".to_string(),
+                VerifyStatus::SizeChanged(_) => "size changed".to_string(),
+                VerifyStatus::Error(message) => message.clone(),
+                VerifyStatus::Ok => continue,
+            };
+            println!("{} ({})", file.entry.file, reason);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{path::{Path, PathBuf}, fs::File, io::Write, time::SystemTime};
+use xml::reader::{EventReader, XmlEvent as ReaderEvent};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
+
+use crate::{Checksum, ChecksumMethod, util::format_system_time_to_rfc3339};
+
+// Struct to hold the metadata of a file for the MediaHashList.
+#[derive(Debug, Clone)]
+pub struct FileMetadata {
+    pub file: String,
+    pub size: u64,
+    pub last_modification_date: SystemTime,
+    // One checksum for every checksum method, written as one element each.
+    pub checksums: Vec<Checksum>,
+    pub hash_date: SystemTime,
+    // True if the file already existed in the destination and was only verified.
+    pub verified: bool,
+}
+
+// A single file entry of a classic mhl file.
+#[derive(Debug, Clone)]
+pub struct MhlEntry {
+    pub file: String,
+    pub size: u64,
+    pub last_modification_date: Option<String>,
+    // Element name and value of every checksum of the file.
+    pub checksums: Vec<(String, String)>,
+}
+
+impl MhlEntry {
+    // Returns the checksums of the entry whose method is supported by rccopy.
+    pub fn known_checksums(&self) -> Vec<Checksum> {
+        self.checksums.iter()
+            .filter_map(|(name, value)| ChecksumMethod::from_mhl_name(name).map(|method| Checksum { method, value: value.clone() }))
+            .collect()
+    }
+}
+
+// Who created a mhl file or report, on which machine and with which tool.
+pub(crate) struct CreatorInfo {
+    pub name: String,
+    pub username: String,
+    pub hostname: String,
+    pub tool: String,
+}
+
+impl CreatorInfo {
+    // Reads the system information.
+    pub fn collect() -> CreatorInfo {
+        CreatorInfo {
+            name: whoami::realname(),
+            username: whoami::username(),
+            hostname: whoami::hostname(),
+            tool: format!("{} ver. {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+// Writes a mhl file to the destination directory.
+pub fn write_mhl_v2(destination_path: &Path, metadata: &[FileMetadata], start_date: &str) -> std::io::Result<()> {
+    let file = File::create(destination_path)?;
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(file);
+
+    write_event(&mut writer, XmlEvent::start_element("hashlist").attr("version", "1.1"))?;
+
+    let creator = CreatorInfo::collect();
+    let finish_date = format_system_time_to_rfc3339(SystemTime::now());
+
+    write_event(&mut writer, XmlEvent::start_element("creatorinfo"))?;
+    write_text_element(&mut writer, "name", &creator.name)?;
+    write_text_element(&mut writer, "username", &creator.username)?;
+    write_text_element(&mut writer, "hostname", &creator.hostname)?;
+    write_text_element(&mut writer, "tool", &creator.tool)?;
+    write_text_element(&mut writer, "startdate", start_date)?;
+    write_text_element(&mut writer, "finishdate", &finish_date)?;
+    write_event(&mut writer, XmlEvent::end_element())?;
+
+    for item in metadata {
+        let file_path = PathBuf::from(&item.file);
+        let relative_path = file_path.strip_prefix(destination_path).unwrap_or(&file_path);
+
+        write_event(&mut writer, XmlEvent::start_element("hash"))?;
+        write_text_element(&mut writer, "file", &relative_path.to_string_lossy())?;
+        write_text_element(&mut writer, "size", &item.size.to_string())?;
+        write_text_element(&mut writer, "lastmodificationdate", &format_system_time_to_rfc3339(item.last_modification_date))?;
+        for checksum in &item.checksums {
+            write_text_element(&mut writer, checksum.method.mhl_name(), &checksum.value)?;
+        }
+        write_text_element(&mut writer, "hashdate", &format_system_time_to_rfc3339(item.hash_date))?;
+        write_event(&mut writer, XmlEvent::end_element())?;
+    }
+
+    write_event(&mut writer, XmlEvent::end_element())?;
+
+    Ok(())
+}
+
+// Reads the file entries of a classic mhl file, as written by write_mhl_v2.
+pub fn read_mhl(mhl_file: &Path) -> std::io::Result<Vec<MhlEntry>> {
+    let reader = EventReader::new(File::open(mhl_file)?);
+    let mut entries: Vec<MhlEntry> = Vec::new();
+    let mut current: Option<MhlEntry> = None;
+    let mut current_element = String::new();
+
+    for event in reader {
+        match event.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))? {
+            ReaderEvent::StartElement { name, .. } => {
+                if name.local_name == "hash" {
+                    current = Some(MhlEntry { file: String::new(), size: 0, last_modification_date: None, checksums: Vec::new() });
+                }
+                current_element = name.local_name;
+            }
+            ReaderEvent::Characters(text) => {
+                if let Some(entry) = current.as_mut() {
+                    match current_element.as_str() {
+                        "file" => entry.file = text,
+                        "size" => entry.size = text.trim().parse().map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid size: {}", text)))?,
+                        "lastmodificationdate" => entry.last_modification_date = Some(text.trim().to_string()),
+                        "hashdate" | "creationdate" => {}
+                        method => {
+                            // C4 ids are base58 and case sensitive, all other checksums are hex.
+                            let checksum = if method == "c4" { text.trim().to_string() } else { text.trim().to_lowercase() };
+                            entry.checksums.push((method.to_string(), checksum));
+                        }
+                    }
+                }
+            }
+            ReaderEvent::EndElement { name } => {
+                if name.local_name == "hash" {
+                    if let Some(entry) = current.take() {
+                        entries.push(entry);
+                    }
+                }
+                current_element.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+// Writes a single xml event, converting writer errors to io errors.
+pub(crate) fn write_event<'a, W: Write, E: Into<XmlEvent<'a>>>(writer: &mut EventWriter<W>, event: E) -> std::io::Result<()> {
+    writer.write(event).map_err(std::io::Error::other)
+}
+
+// Writes an element that only contains text.
+pub(crate) fn write_text_element<W: Write>(writer: &mut EventWriter<W>, name: &str, text: &str) -> std::io::Result<()> {
+    write_event(writer, XmlEvent::start_element(name))?;
+    write_event(writer, XmlEvent::characters(text))?;
+    write_event(writer, XmlEvent::end_element())
+}
@@ -0,0 +1,244 @@
+use std::{path::{Path, PathBuf}, fmt, collections::{BTreeMap, HashMap}};
+
+use crate::util::{format_bytes, mount_point};
+
+// Largest file FAT32 can hold, one byte less than 4 GB.
+const FAT32_MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+// Prefix of the file created in a destination to find out if it ignores case, followed by the process id.
+#[cfg(not(any(target_os = "macos", windows)))]
+const CASE_PROBE_PREFIX: &str = ".rccopy-case-probe-";
+
+// Magic numbers of the file systems in statfs on Linux.
+#[cfg(target_os = "linux")]
+const MSDOS_SUPER_MAGIC: i64 = 0x4d44;
+#[cfg(target_os = "linux")]
+const EXFAT_SUPER_MAGIC: i64 = 0x2011_bab0;
+
+// A problem found before the copy starts that would make it fail halfway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreflightIssue {
+    // The files do not fit on a volume. Destinations on the same volume share its free space.
+    NotEnoughSpace { volume: PathBuf, destinations: Vec<PathBuf>, required: u64, available: u64 },
+    // A file is larger than the file system of a destination allows.
+    FileTooLarge { destination: PathBuf, file: PathBuf, size: u64, filesystem: FileSystem },
+    // Source paths that only differ in upper and lower case, on a destination that does not tell them apart.
+    CaseCollision { destination: PathBuf, paths: Vec<PathBuf> },
+}
+
+impl fmt::Display for PreflightIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightIssue::NotEnoughSpace { volume, destinations, required, available } => {
+                let destinations = destinations.iter().map(|destination| destination.display().to_string()).collect::<Vec<String>>().join(", ");
+                write!(f, "Not enough free space on {} for {}: {} needed, {} available.", volume.display(), destinations, format_bytes(*required), format_bytes(*available))
+            }
+            PreflightIssue::FileTooLarge { destination, file, size, filesystem } => {
+                write!(f, "{} is {}, {} on {} only holds files up to {}.", file.display(), format_bytes(*size), filesystem, destination.display(), format_bytes(filesystem.max_file_size().unwrap_or_default()))
+            }
+            PreflightIssue::CaseCollision { destination, paths } => {
+                let paths = paths.iter().map(|path| path.display().to_string()).collect::<Vec<String>>().join(", ");
+                write!(f, "{} does not tell upper and lower case apart, these would overwrite each other: {}", destination.display(), paths)
+            }
+        }
+    }
+}
+
+// File systems with limits that matter for a copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSystem {
+    Fat,
+    ExFat,
+    Other,
+}
+
+impl fmt::Display for FileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileSystem::Fat => write!(f, "FAT32"),
+            FileSystem::ExFat => write!(f, "exFAT"),
+            FileSystem::Other => write!(f, "the file system"),
+        }
+    }
+}
+
+impl FileSystem {
+    // Largest file the file system can hold, None if it is too large to matter.
+    fn max_file_size(&self) -> Option<u64> {
+        match self {
+            FileSystem::Fat => Some(FAT32_MAX_FILE_SIZE),
+            _ => None,
+        }
+    }
+}
+
+// Checks that every destination can hold the files before anything is written: the free space of each volume,
+// the file size limit of FAT32 and paths that would collide on a file system that ignores case.
+// Files are given with their size. Files that already exist in a destination with the same size are usually not written again and not counted.
+// Paths are the files and directories that are copied, all of them below input_parent.
+// During a dry run nothing is written to the destinations, the case sensitivity is then only guessed from the file system.
+pub fn preflight_check(files: &[(PathBuf, u64)], paths: &[PathBuf], input_parent: &Path, destinations: &[PathBuf], dry_run: bool) -> Vec<PreflightIssue> {
+    let mut issues = Vec::new();
+
+    // Destinations on the same volume share its free space.
+    let mut volumes: BTreeMap<PathBuf, (Vec<PathBuf>, u64)> = BTreeMap::new();
+    for destination in destinations {
+        let mut required: u64 = 0;
+        let mut largest_existing: u64 = 0;
+        for (file, size) in files {
+            let relative_path = file.strip_prefix(input_parent).unwrap_or(file);
+            if destination.join(relative_path).metadata().is_ok_and(|metadata| metadata.len() == *size) {
+                largest_existing = largest_existing.max(*size);
+            } else {
+                required += size;
+            }
+        }
+        // An existing file that does not match is written to a temporary file next to it and only replaces it afterwards.
+        // Files are copied one after the other, so room for the largest of them is enough. Parallel jobs are not taken into account.
+        let volume = volumes.entry(mount_point(destination)).or_default();
+        volume.0.push(destination.clone());
+        volume.1 += required + largest_existing;
+    }
+    for (volume, (destinations, required)) in volumes {
+        // Free space that can not be determined is not checked.
+        let Some(available) = available_space(&destinations[0]) else {
+            continue;
+        };
+        if required > available {
+            issues.push(PreflightIssue::NotEnoughSpace { volume, destinations, required, available });
+        }
+    }
+
+    for destination in destinations {
+        let filesystem = filesystem(destination);
+        if let Some(limit) = filesystem.max_file_size() {
+            for (file, size) in files.iter().filter(|(_, size)| *size > limit) {
+                issues.push(PreflightIssue::FileTooLarge { destination: destination.clone(), file: file.clone(), size: *size, filesystem });
+            }
+        }
+
+        if is_case_insensitive(destination, filesystem, dry_run) {
+            for paths in case_collisions(paths) {
+                issues.push(PreflightIssue::CaseCollision { destination: destination.clone(), paths });
+            }
+        }
+    }
+
+    issues
+}
+
+// Groups of paths that are the same if upper and lower case are ignored.
+fn case_collisions(paths: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for path in paths {
+        groups.entry(path.to_string_lossy().to_lowercase()).or_default().push(path.clone());
+    }
+    let mut collisions: Vec<Vec<PathBuf>> = groups.into_values().filter(|group| group.len() > 1).collect();
+    collisions.sort();
+    collisions
+}
+
+// Bytes available to the user on the volume of a path.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // The types of the fields differ between platforms.
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn filesystem(path: &Path) -> FileSystem {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return FileSystem::Other;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return FileSystem::Other;
+    }
+    // The type of f_type differs between architectures.
+    match stat.f_type as i64 {
+        MSDOS_SUPER_MAGIC => FileSystem::Fat,
+        EXFAT_SUPER_MAGIC => FileSystem::ExFat,
+        _ => FileSystem::Other,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn filesystem(path: &Path) -> FileSystem {
+    use std::{ffi::{CStr, CString}, os::unix::ffi::OsStrExt};
+
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return FileSystem::Other;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return FileSystem::Other;
+    }
+    let name = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    match name.to_bytes() {
+        b"msdos" => FileSystem::Fat,
+        b"exfat" => FileSystem::ExFat,
+        _ => FileSystem::Other,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn filesystem(_path: &Path) -> FileSystem {
+    FileSystem::Other
+}
+
+// True if the file system of a path does not tell upper and lower case apart.
+// FAT and exFAT never do, on macOS APFS and HFS+ volumes can be either.
+#[cfg(target_os = "macos")]
+fn is_case_insensitive(path: &Path, filesystem: FileSystem, _dry_run: bool) -> bool {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    if filesystem != FileSystem::Other {
+        return true;
+    }
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::pathconf(c_path.as_ptr(), libc::_PC_CASE_SENSITIVE) == 0 }
+}
+
+#[cfg(windows)]
+fn is_case_insensitive(_path: &Path, _filesystem: FileSystem, _dry_run: bool) -> bool {
+    true
+}
+
+// The file system type does not tell: SMB shares, NTFS and casefolded ext4 directories ignore case as well.
+// A probe file is created in the destination and looked up with its name in upper case. If that fails, and during a dry run, only FAT and exFAT count.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn is_case_insensitive(path: &Path, filesystem: FileSystem, dry_run: bool) -> bool {
+    if dry_run {
+        return filesystem != FileSystem::Other;
+    }
+    probe_case_insensitive(path).unwrap_or(filesystem != FileSystem::Other)
+}
+
+// Creates a hidden probe file in a directory and checks if it is found under its name in upper case. The probe is removed again.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn probe_case_insensitive(dir: &Path) -> std::io::Result<bool> {
+    use std::fs;
+
+    let name = format!("{}{}", CASE_PROBE_PREFIX, std::process::id());
+    let probe = dir.join(&name);
+    fs::OpenOptions::new().write(true).create_new(true).open(&probe)?;
+    let found = fs::symlink_metadata(dir.join(name.to_uppercase())).is_ok();
+    let _ = fs::remove_file(&probe);
+    Ok(found)
+}
@@ -0,0 +1,201 @@
+use std::{path::{Path, PathBuf}, fs::File, fmt, io::{BufWriter, Write}, sync::Arc, time::Duration};
+
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+
+use crate::{Checksum, CopyResult, DestinationResult};
+
+// How the progress of a copy job is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ProgressMode {
+    // Human readable lines and transfer speeds.
+    #[default]
+    Text,
+    // JSON events besides the human readable lines, without transfer speeds. The command line tool prints them on stdout and the lines on stderr.
+    Json,
+}
+
+// Output of a copy job or verify run. The library prints nothing itself, everything is passed to a callback, see CopyJob::on_event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    // A line of the human readable output.
+    Line(String),
+    // A warning or error of the human readable output, e.g. "Error: Could not copy file: ...".
+    ErrorLine(String),
+    // The current transfer speed while a file is copied or verified, e.g. "Transfer speed:" and "123.45 MB/s".
+    // Only sent with ProgressMode::Text and a single job. Every update replaces the last one.
+    Speed { label: String, speed: String },
+    // The transfer speed of a file is done. With keep the last speed stays visible, otherwise it is removed.
+    SpeedDone { keep: bool },
+    // A progress event as JSON object, e.g. {"event":"file_started","source":"...",...}. Only sent with ProgressMode::Json.
+    Json(Value),
+}
+
+// Callback receiving the events of a job. Events are dropped if none is set.
+#[derive(Clone, Default)]
+pub(crate) struct EventCallback(Option<Arc<dyn Fn(Event) + Send + Sync>>);
+
+impl EventCallback {
+    pub fn new(callback: impl Fn(Event) + Send + Sync + 'static) -> EventCallback {
+        EventCallback(Some(Arc::new(callback)))
+    }
+
+    pub fn send(&self, event: Event) {
+        if let Some(callback) = &self.0 {
+            callback(event);
+        }
+    }
+}
+
+impl fmt::Debug for EventCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "EventCallback(Some(..))" } else { "EventCallback(None)" })
+    }
+}
+
+// Format of the report written after a copy job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Json,
+}
+
+// Outcome of a single file in a single destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordStatus {
+    Copied,
+    Skipped,
+    Failed,
+}
+
+impl RecordStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecordStatus::Copied => "copied",
+            RecordStatus::Skipped => "skipped",
+            RecordStatus::Failed => "failed",
+        }
+    }
+}
+
+// Why a file failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    // The source file could not be read.
+    Source,
+    // The destination file could not be written, synced, read back or moved into place.
+    Destination,
+    // The checksums of the source and the destination do not match.
+    Mismatch,
+}
+
+impl FailureKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FailureKind::Source => "source",
+            FailureKind::Destination => "destination",
+            FailureKind::Mismatch => "mismatch",
+        }
+    }
+}
+
+// What happened to a single file in a single destination.
+#[derive(Debug, Clone)]
+pub struct FileRecord {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub size: u64,
+    // Checksums of the source, empty without a checksum method or if the file failed before they were calculated.
+    pub checksums: Vec<Checksum>,
+    // Time spent on the file, for all destinations together.
+    pub duration: Duration,
+    pub status: RecordStatus,
+    // Set if the file failed.
+    pub failure: Option<FailureKind>,
+    pub error: Option<String>,
+}
+
+// Builds a progress event with its name in "event", followed by the given fields.
+pub fn json_event(event: &str, fields: Value) -> Event {
+    let mut object = Map::new();
+    object.insert("event".to_string(), Value::from(event));
+    if let Value::Object(fields) = fields {
+        object.extend(fields);
+    }
+    Event::Json(Value::Object(object))
+}
+
+// Checksums as an object of method and value, e.g. {"md5": "...", "xxhash64": "..."}.
+pub fn checksums_json(checksums: &[Checksum]) -> Value {
+    Value::Object(checksums.iter().map(|checksum| (checksum.method.name().to_string(), Value::from(checksum.value.clone()))).collect())
+}
+
+pub fn path_json(path: &Path) -> Value {
+    Value::from(path.to_string_lossy())
+}
+
+fn paths_json(paths: &[PathBuf]) -> Value {
+    Value::Array(paths.iter().map(|path| path_json(path)).collect())
+}
+
+pub fn record_json(record: &FileRecord) -> Value {
+    json!({
+        "source": path_json(&record.source),
+        "destination": path_json(&record.destination),
+        "size": record.size,
+        "checksums": checksums_json(&record.checksums),
+        "duration": record.duration.as_secs_f64(),
+        "status": record.status.name(),
+        "failure": record.failure.map(|failure| failure.name()),
+        "error": record.error,
+    })
+}
+
+// Number of copied, skipped and failed files and the bytes copied, for the reports and the job_finished event.
+pub fn destination_totals_json(destination: &DestinationResult) -> Value {
+    json!({
+        "path": path_json(&destination.path),
+        "copied": destination.copied.len(),
+        "skipped": destination.skipped.len(),
+        "failed": destination.failed.len(),
+        "bytes_copied": destination.bytes_copied(),
+    })
+}
+
+// Writes the report of a finished copy job as a JSON file.
+pub fn write_json_report(path: &Path, input: &Path, result: &CopyResult) -> std::io::Result<()> {
+    let destinations: Vec<Value> = result.destinations.iter().map(|destination| {
+        let mut value = destination_totals_json(destination);
+        value["mhl_file"] = destination.mhl_file.as_deref().map(path_json).unwrap_or(Value::Null);
+        value["ascmhl_generation"] = destination.ascmhl_generation.as_deref().map(path_json).unwrap_or(Value::Null);
+        value["html_report"] = destination.html_report.as_deref().map(path_json).unwrap_or(Value::Null);
+        value["files"] = Value::Array(destination.records.iter().map(record_json).collect());
+        value["failed_files"] = paths_json(&destination.failed);
+        value["links"] = paths_json(&destination.links);
+        value["special_files"] = paths_json(&destination.special_files);
+        value["metadata_failures"] = Value::Array(destination.metadata_failures.iter()
+            .map(|(file, error)| json!({ "path": path_json(file), "error": error }))
+            .collect());
+        value
+    }).collect();
+
+    let report = json!({
+        "source": path_json(input),
+        "start_date": result.start_date,
+        "duration": result.duration.as_secs_f64(),
+        "dry_run": result.dry_run,
+        "success": !result.had_errors(),
+        "stopped": result.stopped,
+        "destinations": destinations,
+        "excluded": paths_json(&result.excluded),
+        "excluded_dirs": paths_json(&result.excluded_dirs),
+        "skipped_links": paths_json(&result.skipped_links),
+        "skipped_special_files": Value::Array(result.skipped_special_files.iter()
+            .map(|(file, kind)| json!({ "path": path_json(file), "kind": kind.to_string() }))
+            .collect()),
+    });
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, &report)?;
+    writeln!(writer)?;
+    writer.flush()
+}
@@ -0,0 +1,219 @@
+use std::{path::{Path, PathBuf}, fs, fmt};
+
+use clap::ValueEnum;
+
+use crate::filter::Filter;
+
+// Files found in a source directory.
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub files: Vec<PathBuf>,
+    // Files that were excluded by the filter.
+    pub excluded: Vec<PathBuf>,
+    // Directories that were excluded by the filter. Their contents are not searched.
+    pub excluded_dirs: Vec<PathBuf>,
+    // Directories that are copied, starting with the root. Includes directories without any entries.
+    pub dirs: Vec<PathBuf>,
+    // Symbolic links that are copied as links.
+    pub links: Vec<PathBuf>,
+    // Symbolic links that were skipped: all links in skip mode, and broken links or links leading back into a parent directory in follow mode.
+    pub skipped_links: Vec<PathBuf>,
+    // Entries that are neither files, directories nor symbolic links.
+    pub special_files: Vec<(PathBuf, SpecialKind)>,
+    // Files and directories that could not be read, with the error. The contents of unreadable directories are missing from the scan.
+    pub unreadable: Vec<(PathBuf, std::io::Error)>,
+}
+
+// Kind of an entry that is neither a file, a directory nor a symbolic link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialKind {
+    NamedPipe,
+    Socket,
+    BlockDevice,
+    CharDevice,
+    Unknown,
+}
+
+impl SpecialKind {
+    #[cfg(unix)]
+    fn of(file_type: &fs::FileType) -> SpecialKind {
+        use std::os::unix::fs::FileTypeExt;
+
+        if file_type.is_fifo() {
+            SpecialKind::NamedPipe
+        } else if file_type.is_socket() {
+            SpecialKind::Socket
+        } else if file_type.is_block_device() {
+            SpecialKind::BlockDevice
+        } else if file_type.is_char_device() {
+            SpecialKind::CharDevice
+        } else {
+            SpecialKind::Unknown
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(_file_type: &fs::FileType) -> SpecialKind {
+        SpecialKind::Unknown
+    }
+
+    // True if the entry can be recreated in the destination. Sockets only exist while a program is listening on them.
+    pub fn can_recreate(&self) -> bool {
+        matches!(self, SpecialKind::NamedPipe | SpecialKind::BlockDevice | SpecialKind::CharDevice)
+    }
+}
+
+impl fmt::Display for SpecialKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecialKind::NamedPipe => write!(f, "named pipe"),
+            SpecialKind::Socket => write!(f, "socket"),
+            SpecialKind::BlockDevice => write!(f, "block device"),
+            SpecialKind::CharDevice => write!(f, "character device"),
+            SpecialKind::Unknown => write!(f, "unknown file type"),
+        }
+    }
+}
+
+// How named pipes, sockets and devices in the source directory are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SpecialFileMode {
+    // Do not copy them.
+    #[default]
+    Skip,
+    // Create a new named pipe or device node with the same type, permissions and device number in the destination.
+    // Sockets can not be recreated and are always skipped. Creating device nodes usually requires root.
+    Recreate,
+}
+
+// How symbolic links in the source directory are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SymlinkMode {
+    // Recreate the link in the destination, pointing to the same target. Links are not listed in the mhl files.
+    CopyAsLink,
+    // Copy the file or directory the link points to. Links that lead back into a parent directory are skipped.
+    #[default]
+    Follow,
+    // Do not copy links at all.
+    Skip,
+}
+
+// Searches the given directory recursively for files and returns a vector of the files. The macOS system files and symbolic links are skipped.
+// Subdirectories that can not be read are left out.
+pub fn get_files_in_directory(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    Ok(scan_directory(dir, &Filter::default(), SymlinkMode::CopyAsLink)?.files)
+}
+
+// Searches the given directory recursively for files and sorts them into copied and excluded files. Excluded directories are skipped as a whole.
+// Only an unreadable root directory is an error, everything below it that can not be read is listed in unreadable and the scan goes on.
+pub fn scan_directory(root: &Path, filter: &Filter, symlinks: SymlinkMode) -> std::io::Result<Scan> {
+    let mut scan = Scan { dirs: vec![root.to_path_buf()], ..Scan::default() };
+    let mut parents = vec![dir_id(root, &fs::metadata(root)?)?];
+    scan_dir(root, root, filter, symlinks, &mut parents, &mut scan)?;
+    Ok(scan)
+}
+
+// Parents holds the ids of the directories above the current one, to detect symbolic links that lead back into them.
+fn scan_dir(root: &Path, dir: &Path, filter: &Filter, symlinks: SymlinkMode, parents: &mut Vec<DirId>, scan: &mut Scan) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                scan.unreadable.push((dir.to_path_buf(), e));
+                continue;
+            }
+        };
+        if let Err(e) = scan_entry(root, &entry, filter, symlinks, parents, scan) {
+            scan.unreadable.push((entry.path(), e));
+        }
+    }
+
+    Ok(())
+}
+
+// Sorts a single entry of a directory into the scan, and searches it if it is a directory.
+fn scan_entry(root: &Path, entry: &fs::DirEntry, filter: &Filter, symlinks: SymlinkMode, parents: &mut Vec<DirId>, scan: &mut Scan) -> std::io::Result<()> {
+    let path = entry.path();
+    let relative_path = path.strip_prefix(root).unwrap_or(&path);
+
+    // The file type of the entry itself, symbolic links are not followed.
+    let mut file_type = entry.file_type()?;
+
+    if file_type.is_symlink() {
+        match symlinks {
+            SymlinkMode::Skip => {
+                // Excluded links are listed as excluded, not as skipped. A link to a directory is matched like a directory.
+                let is_dir = fs::metadata(&path).is_ok_and(|metadata| metadata.is_dir());
+                if !filter.is_excluded(relative_path, is_dir) {
+                    scan.skipped_links.push(path);
+                } else if is_dir {
+                    scan.excluded_dirs.push(path);
+                } else {
+                    scan.excluded.push(path);
+                }
+                return Ok(());
+            }
+            SymlinkMode::CopyAsLink => {
+                if filter.is_excluded(relative_path, false) {
+                    scan.excluded.push(path);
+                } else {
+                    scan.links.push(path);
+                }
+                return Ok(());
+            }
+            SymlinkMode::Follow => {
+                // Links to files that do not exist can not be followed.
+                let Ok(metadata) = fs::metadata(&path) else {
+                    scan.skipped_links.push(path);
+                    return Ok(());
+                };
+                if metadata.is_dir() && parents.contains(&dir_id(&path, &metadata)?) {
+                    scan.skipped_links.push(path);
+                    return Ok(());
+                }
+                file_type = metadata.file_type();
+            }
+        }
+    }
+
+    if file_type.is_dir() {
+        if filter.is_excluded(relative_path, true) {
+            scan.excluded_dirs.push(path);
+            return Ok(());
+        }
+        parents.push(dir_id(&path, &fs::metadata(&path)?)?);
+        let result = scan_dir(root, &path, filter, symlinks, parents, scan);
+        parents.pop();
+        result?;
+        // Directories that can not be read are not created in the destinations.
+        scan.dirs.push(path);
+    } else if filter.is_excluded(relative_path, false) {
+        scan.excluded.push(path);
+    } else if file_type.is_file() {
+        scan.files.push(path);
+    } else {
+        // Named pipes, sockets and devices are never read, opening a named pipe would block forever.
+        scan.special_files.push((path, SpecialKind::of(&file_type)));
+    }
+
+    Ok(())
+}
+
+// Identifies a directory independent of the path it was reached by: its device and inode number.
+#[cfg(unix)]
+type DirId = (u64, u64);
+
+#[cfg(unix)]
+fn dir_id(_path: &Path, metadata: &fs::Metadata) -> std::io::Result<DirId> {
+    use std::os::unix::fs::MetadataExt;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+// Without device and inode numbers the canonical path identifies a directory.
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path, _metadata: &fs::Metadata) -> std::io::Result<DirId> {
+    fs::canonicalize(path)
+}
@@ -0,0 +1,147 @@
+use std::{path::{Path, PathBuf}, fs, time::{SystemTime, Instant, Duration}, collections::VecDeque};
+use chrono::{DateTime, Utc, SecondsFormat};
+
+use crate::Event;
+
+// Formats a SystemTime to a RFC3339 string.
+pub fn format_system_time_to_rfc3339(st: SystemTime) -> String {
+    let datetime: DateTime<Utc> = st.into();
+    datetime.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// Returns the mount point of the volume a path is on, e.g. /Volumes/A001 for /Volumes/A001/Clips.
+// The mount point is the topmost directory on the same device.
+#[cfg(unix)]
+pub fn mount_point(path: &Path) -> PathBuf {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(path) = fs::canonicalize(path) else {
+        return path.to_path_buf();
+    };
+    let Ok(device) = fs::metadata(&path).map(|metadata| metadata.dev()) else {
+        return path;
+    };
+
+    let mut mount_point = path.as_path();
+    for ancestor in path.ancestors().skip(1) {
+        match fs::metadata(ancestor) {
+            Ok(metadata) if metadata.dev() == device => mount_point = ancestor,
+            _ => break,
+        }
+    }
+    mount_point.to_path_buf()
+}
+
+// Without device numbers the drive is taken as volume.
+#[cfg(not(unix))]
+pub fn mount_point(path: &Path) -> PathBuf {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    path.ancestors().last().map(Path::to_path_buf).unwrap_or(path)
+}
+
+// Formats Bytes/s to a human readable string.
+pub fn format_bytes_per_second(bytes: u64) -> String {
+    format!("{}/s", format_bytes(bytes))
+}
+
+// Formats a size in bytes to a human readable string.
+pub fn format_bytes(bytes: u64) -> String {
+    let kb: u64 = 1024;
+    let mb: u64 = kb * 1024;
+    let gb: u64 = mb * 1024;
+    let tb: u64 = gb * 1024;
+
+    if bytes < kb {
+        format!("{} B", bytes)
+    } else if bytes < mb {
+        format!("{:.2} KB", bytes as f64 / kb as f64)
+    } else if bytes < gb {
+        format!("{:.2} MB", bytes as f64 / mb as f64)
+    } else if bytes < tb {
+        format!("{:.2} GB", bytes as f64 / gb as f64)
+    } else {
+        format!("{:.2} TB", bytes as f64 / tb as f64)
+    }
+}
+
+// Formats a duration as hours, minutes and seconds, e.g. "1:02:03" or "0:00:04.21" below a minute.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds < 60 {
+        return format!("0:00:{:05.2}", duration.as_secs_f64());
+    }
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Measures the transfer speed of a copy or checksum process, smoothed over the last readings.
+pub struct TransferSpeed {
+    readings: VecDeque<f64>,
+    bytes_since_last_reading: u64,
+    last_reading_time: Instant,
+}
+
+impl TransferSpeed {
+    // Use last 10 readings to calculate the speed
+    const WINDOW_SIZE: usize = 10;
+
+    pub fn new() -> TransferSpeed {
+        TransferSpeed {
+            readings: VecDeque::new(),
+            bytes_since_last_reading: 0,
+            last_reading_time: Instant::now(),
+        }
+    }
+
+    // Adds the given amount of bytes. Returns the formatted speed every 100 ms, None otherwise.
+    pub fn add(&mut self, bytes: u64) -> Option<String> {
+        self.bytes_since_last_reading += bytes;
+
+        let elapsed = self.last_reading_time.elapsed();
+        if elapsed <= Duration::from_millis(100) {
+            return None;
+        }
+
+        let bytes_per_second = self.bytes_since_last_reading as f64 / elapsed.as_secs_f64();
+
+        // Use a moving window to smooth the transfer speed
+        if self.readings.len() >= Self::WINDOW_SIZE {
+            self.readings.pop_front();
+        }
+        self.readings.push_back(bytes_per_second);
+
+        let avg_bytes_per_second: f64 = self.readings.iter().sum::<f64>() / self.readings.len() as f64;
+
+        self.last_reading_time = Instant::now();
+        self.bytes_since_last_reading = 0;
+
+        Some(format_bytes_per_second(avg_bytes_per_second as u64))
+    }
+}
+
+// Sends the transfer speed as Speed events, every update replaces the last one.
+pub struct SpeedLine<'a> {
+    label: String,
+    speed: TransferSpeed,
+    on_event: &'a dyn Fn(Event),
+}
+
+impl<'a> SpeedLine<'a> {
+    // Sends a placeholder for the transfer speed.
+    pub fn new(label: impl Into<String>, on_event: &'a dyn Fn(Event)) -> SpeedLine<'a> {
+        let label = label.into();
+        on_event(Event::Speed { label: label.clone(), speed: "---.-- MB/s".to_string() });
+        SpeedLine { label, speed: TransferSpeed::new(), on_event }
+    }
+
+    // Send the transfer speed every 100 ms.
+    pub fn update(&mut self, bytes: u64) {
+        if let Some(speed) = self.speed.add(bytes) {
+            (self.on_event)(Event::Speed { label: self.label.clone(), speed });
+        }
+    }
+
+    // Removes the speed.
+    pub fn clear(&self) {
+        (self.on_event)(Event::SpeedDone { keep: false });
+    }
+}
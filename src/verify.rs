@@ -0,0 +1,228 @@
+use std::{path::{Path, PathBuf}, collections::{BTreeSet, HashSet}};
+
+use crate::{ChecksumMethod, Error, Event, MhlEntry, read_mhl, process_checksum, ascmhl::ASCMHL_FOLDER, journal::STATE_FOLDER, scan::get_files_in_directory, util::SpeedLine};
+
+// Result of verifying a single file against its mhl entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyStatus {
+    Ok,
+    Mismatch,
+    Missing,
+    SizeChanged(u64),
+    Error(String),
+}
+
+// A file listed in the mhl file and the result of its verification.
+#[derive(Debug, Clone)]
+pub struct VerifiedFile {
+    pub entry: MhlEntry,
+    pub status: VerifyStatus,
+}
+
+// Result of verifying a directory against a mhl file.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub files: Vec<VerifiedFile>,
+    // Files that are on disk but not listed in the mhl file.
+    pub unlisted_files: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    // Returns the files that are missing, changed or do not match.
+    pub fn failed_files(&self) -> impl Iterator<Item = &VerifiedFile> {
+        self.files.iter().filter(|file| file.status != VerifyStatus::Ok)
+    }
+
+    // True if every listed file exists and its checksum matches.
+    pub fn is_ok(&self) -> bool {
+        self.failed_files().next().is_none()
+    }
+}
+
+// Verifies the files listed in a mhl file against the files in the root directory.
+// The output is passed to on_event as lines and transfer speeds, nothing is printed.
+pub fn verify_mhl(mhl_file: &Path, root: &Path, on_event: impl Fn(Event)) -> Result<VerifyReport, Error> {
+    let entries = read_mhl(mhl_file).map_err(|source| Error::Mhl { path: mhl_file.to_path_buf(), source })?;
+
+    let total_files = entries.len();
+    let mut report = VerifyReport::default();
+    let mut listed_files: HashSet<PathBuf> = HashSet::new();
+    let mut top_level_folders: BTreeSet<PathBuf> = BTreeSet::new();
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        let file = root.join(&entry.file);
+        listed_files.insert(file.clone());
+        let mut components = Path::new(&entry.file).components();
+        if let (Some(folder), Some(_)) = (components.next(), components.next()) {
+            top_level_folders.insert(PathBuf::from(folder.as_os_str()));
+        }
+
+        on_event(Event::Line("-------------------------".to_string()));
+        on_event(Event::Line(format!("{} / {}: {}", index + 1, total_files, file.display())));
+
+        let status = verify_entry(&entry, &file, &on_event);
+        let line = match &status {
+            VerifyStatus::Ok => format!("OK: {}", format_entry_checksums(&entry)),
+            VerifyStatus::Mismatch => format!("Error: Checksum mismatch. Expected {}.", format_entry_checksums(&entry)),
+            VerifyStatus::Missing => "Error: File is missing.".to_string(),
+            VerifyStatus::SizeChanged(size) => format!("Error: File size changed. Expected {} bytes, found {} bytes.", entry.size, size),
+            VerifyStatus::Error(message) => format!("Error: {}", message),
+        };
+        on_event(Event::Line(line));
+
+        report.files.push(VerifiedFile { entry, status });
+    }
+
+    // Look for files that are on disk but not in the mhl file. Hash lists and the state of rccopy are not listed in it.
+    // Only the top level folders listed in the mhl file are searched, other copies next to them in the same root have their own mhl files.
+    let mut files: Vec<PathBuf> = Vec::new();
+    for folder in top_level_folders {
+        let folder = root.join(folder);
+        if folder.is_dir() {
+            files.extend(get_files_in_directory(&folder).map_err(|source| Error::Io { path: folder.clone(), source })?);
+        }
+    }
+    for file in files {
+        let relative_path = file.strip_prefix(root).unwrap_or(&file);
+        let is_hash_list_or_state = file.extension().is_some_and(|extension| extension == "mhl")
+            || relative_path.components().any(|component| component.as_os_str() == ASCMHL_FOLDER || component.as_os_str() == STATE_FOLDER);
+        if !is_hash_list_or_state && !listed_files.contains(&file) {
+            report.unlisted_files.push(file);
+        }
+    }
+    report.unlisted_files.sort();
+
+    Ok(report)
+}
+
+// Verifies a single file against its mhl entry.
+fn verify_entry(entry: &MhlEntry, file: &Path, on_event: &dyn Fn(Event)) -> VerifyStatus {
+    if !file.exists() {
+        return VerifyStatus::Missing;
+    }
+
+    let size = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return VerifyStatus::Error(e.to_string()),
+    };
+    if size != entry.size {
+        return VerifyStatus::SizeChanged(size);
+    }
+
+    if entry.checksums.is_empty() {
+        return VerifyStatus::Error("No checksum listed.".to_string());
+    }
+
+    // Map the mhl element names to the checksum methods. All checksums are calculated in one pass.
+    let mut checksum_methods: Vec<ChecksumMethod> = Vec::new();
+    for (name, _) in &entry.checksums {
+        match ChecksumMethod::from_mhl_name(name) {
+            Some(method) => checksum_methods.push(method),
+            None => return VerifyStatus::Error(format!("Unsupported checksum method: {}", name)),
+        }
+    }
+
+    let names: Vec<&str> = checksum_methods.iter().map(ChecksumMethod::name).collect();
+    let mut speed_line = SpeedLine::new(format!("Verifying checksum... ({}) Speed:", names.join(", ")), on_event);
+    let checksums = process_checksum(file, &checksum_methods, &mut |bytes| speed_line.update(bytes));
+    speed_line.clear();
+
+    match checksums {
+        Ok(checksums) if checksums.iter().zip(&entry.checksums).all(|(checksum, (_, expected))| checksum.value == *expected) => VerifyStatus::Ok,
+        Ok(_) => VerifyStatus::Mismatch,
+        Err(e) => VerifyStatus::Error(e.to_string()),
+    }
+}
+
+// Formats the checksums of a mhl entry for the output.
+fn format_entry_checksums(entry: &MhlEntry) -> String {
+    entry.checksums.iter().map(|(name, value)| format!("{} ({})", value, name)).collect::<Vec<String>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, time::{Duration, SystemTime, UNIX_EPOCH}};
+    use crate::{FileMetadata, write_mhl_v2, util::format_system_time_to_rfc3339};
+
+    const CHECKSUM_METHODS: [ChecksumMethod; 2] = [ChecksumMethod::Xxhash64, ChecksumMethod::C4];
+
+    // Creates a root directory with a few files and a mhl file listing them.
+    fn root_with_mhl(name: &str) -> (PathBuf, PathBuf, Vec<FileMetadata>) {
+        let root = std::env::temp_dir().join(format!("rccopy-verify-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("A001/Clips")).unwrap();
+
+        let mut metadata: Vec<FileMetadata> = Vec::new();
+        for (file, content) in [("A001/Clips/A001C001.mov", "clip"), ("A001/A001.RMD", "sidecar"), ("empty", "")] {
+            let path = root.join(file);
+            fs::write(&path, content).unwrap();
+            metadata.push(FileMetadata {
+                file: file.to_string(),
+                size: content.len() as u64,
+                last_modification_date: UNIX_EPOCH + Duration::from_secs(1700000000),
+                checksums: process_checksum(&path, &CHECKSUM_METHODS, &mut |_| {}).unwrap(),
+                hash_date: SystemTime::now(),
+                verified: false,
+            });
+        }
+
+        let mhl_file = root.join(format!("{}.mhl", name));
+        write_mhl_v2(&mhl_file, &metadata, "2024-01-01T12:00:00Z").unwrap();
+        (root, mhl_file, metadata)
+    }
+
+    #[test]
+    fn reads_written_mhl() {
+        let (root, mhl_file, metadata) = root_with_mhl("read");
+
+        let entries = read_mhl(&mhl_file).unwrap();
+        assert_eq!(entries.len(), metadata.len());
+        for (entry, item) in entries.iter().zip(&metadata) {
+            assert_eq!(entry.file, item.file);
+            assert_eq!(entry.size, item.size);
+            assert_eq!(entry.last_modification_date, Some(format_system_time_to_rfc3339(item.last_modification_date)));
+            assert_eq!(entry.known_checksums(), item.checksums);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn verifies_against_written_mhl() {
+        let (root, mhl_file, _) = root_with_mhl("verify");
+
+        let report = verify_mhl(&mhl_file, &root, |_| {}).unwrap();
+        assert!(report.is_ok());
+        assert!(report.unlisted_files.is_empty());
+
+        fs::write(root.join("A001/Clips/A001C001.mov"), "CLIP").unwrap();
+        fs::remove_file(root.join("A001/A001.RMD")).unwrap();
+        fs::write(root.join("empty"), "not empty").unwrap();
+        fs::write(root.join("A001/Clips/A001C002.mov"), "stray").unwrap();
+        fs::create_dir_all(root.join(STATE_FOLDER)).unwrap();
+        fs::write(root.join(STATE_FOLDER).join("journal"), "").unwrap();
+
+        let report = verify_mhl(&mhl_file, &root, |_| {}).unwrap();
+        let statuses: Vec<&VerifyStatus> = report.files.iter().map(|file| &file.status).collect();
+        assert_eq!(statuses, [&VerifyStatus::Mismatch, &VerifyStatus::Missing, &VerifyStatus::SizeChanged(9)]);
+        assert_eq!(report.unlisted_files, [root.join("A001/Clips/A001C002.mov")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sibling_folders_are_not_unlisted() {
+        let (root, mhl_file, _) = root_with_mhl("siblings");
+        fs::create_dir_all(root.join("B001/Clips")).unwrap();
+        fs::write(root.join("B001/Clips/B001C001.mov"), "other card").unwrap();
+        fs::write(root.join("B001_2024-01-01_120000.mhl"), "").unwrap();
+        fs::write(root.join("A001/A001C003.mov"), "stray").unwrap();
+
+        let report = verify_mhl(&mhl_file, &root, |_| {}).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.unlisted_files, [root.join("A001/A001C003.mov")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}